@@ -7,8 +7,21 @@ from .ragged_buffer import (
     RaggedBufferI64,
     RaggedBufferBool,
     translate_rotate,
+    padpack_segment_ids,
+    padpack_attention_mask,
+    inverse_permutation,
 )
 
+try:
+    from .ragged_buffer import (
+        SharedRaggedBufferF32,
+        SharedRaggedBufferI64,
+        SharedRaggedBufferBool,
+    )
+except ImportError:
+    # Shared memory buffers are only available on unix platforms
+    pass
+
 ScalarType = TypeVar("ScalarType", bound=np.generic)
 
 
@@ -1,5 +1,5 @@
 import numpy as np
-from typing import Any, Generic, Tuple, TypeVar, Union, overload, List, Optional
+from typing import Any, ClassVar, Generic, Tuple, TypeVar, Union, overload, List, Optional, Literal
 from numpy.typing import NDArray
 import numpy as np
 
@@ -13,6 +13,39 @@ class RaggedBuffer(Generic[ScalarType]):
     def from_flattened(
         cls, flattened: NDArray[ScalarType], lengths: NDArray[np.int64]
     ) -> RaggedBuffer[ScalarType]: ...
+    @overload
+    @classmethod
+    def from_segment_ids(
+        cls,
+        flattened: NDArray[ScalarType],
+        segment_ids: NDArray[np.int64],
+        num_segments: int,
+        return_permutation: Literal[False] = False,
+    ) -> RaggedBuffer[ScalarType]: ...
+    @overload
+    @classmethod
+    def from_segment_ids(
+        cls,
+        flattened: NDArray[ScalarType],
+        segment_ids: NDArray[np.int64],
+        num_segments: int,
+        return_permutation: Literal[True],
+    ) -> Tuple[RaggedBuffer[ScalarType], NDArray[np.int64]]: ...
+    @classmethod
+    def from_padded(
+        cls, padded: NDArray[ScalarType], lengths: NDArray[np.int64]
+    ) -> RaggedBuffer[ScalarType]: ...
+    @classmethod
+    def from_padded_mask(
+        cls, padded: NDArray[ScalarType], mask: NDArray[np.bool_]
+    ) -> RaggedBuffer[ScalarType]: ...
+    @classmethod
+    def from_padpacked(
+        cls,
+        packed: NDArray[ScalarType],
+        inverse_index: NDArray[np.int64],
+        lengths: NDArray[np.int64],
+    ) -> RaggedBuffer[ScalarType]: ...
     def push(self, x: NDArray[ScalarType]) -> None: ...
     def push_empty(self) -> None: ...
     def extend(self, x: RaggedBuffer[ScalarType]) -> None: ...
@@ -23,6 +56,16 @@ class RaggedBuffer(Generic[ScalarType]):
     @overload
     def size1(self, i: int) -> int: ...
     def size2(self) -> int: ...
+    def cu_seqlens(self) -> NDArray[np.int64]: ...
+    def max_seqlen(self) -> int: ...
+    def segment_ids(self) -> NDArray[np.int64]: ...
+    def minibatches(
+        self,
+        seed: int,
+        batch_size: Optional[int] = None,
+        max_items: Optional[int] = None,
+        buckets: Optional[int] = None,
+    ) -> List[NDArray[np.int64]]: ...
     @overload
     def __add__(self, other: RaggedBuffer[ScalarType]) -> RaggedBuffer[ScalarType]: ...
     @overload
@@ -30,37 +73,178 @@ class RaggedBuffer(Generic[ScalarType]):
     @overload
     def __add__(self, other: float) -> RaggedBuffer[np.float32]: ...
     @overload
+    def __add__(self, other: NDArray[ScalarType]) -> RaggedBuffer[ScalarType]: ...
+    @overload
     def __mul__(self, other: RaggedBuffer[ScalarType]) -> RaggedBuffer[ScalarType]: ...
     @overload
     def __mul__(self, other: int) -> RaggedBuffer[ScalarType]: ...
     @overload
     def __mul__(self, other: float) -> RaggedBuffer[np.float32]: ...
+    @overload
+    def __mul__(self, other: NDArray[ScalarType]) -> RaggedBuffer[ScalarType]: ...
     def __getitem__(
         self,
         i: Union[
             int,
+            slice,
+            List[int],
+            List[bool],
             NDArray[np.int64],
-            Tuple[Union[int, List[int], slice, NDArray[np.int64]], ...],
+            NDArray[np.bool_],
+            Tuple[
+                Union[
+                    int,
+                    List[int],
+                    List[bool],
+                    slice,
+                    NDArray[np.int64],
+                    NDArray[np.bool_],
+                    RaggedBufferI64,
+                    "ellipsis",
+                ],
+                ...,
+            ],
         ],
-    ) -> RaggedBuffer[ScalarType]: ...
+    ) -> Union[RaggedBuffer[ScalarType], NDArray[ScalarType]]: ...
+    def __setitem__(
+        self,
+        i: Union[
+            int,
+            NDArray[np.int64],
+            slice,
+            List[int],
+            List[bool],
+            NDArray[np.bool_],
+            RaggedBufferBool,
+            Tuple[
+                Union[
+                    int,
+                    List[int],
+                    List[bool],
+                    slice,
+                    NDArray[np.int64],
+                    NDArray[np.bool_],
+                    "ellipsis",
+                ],
+                ...,
+            ],
+        ],
+        value: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float, bool],
+    ) -> None: ...
     def __eq__(self, other: Any) -> bool: ...
     def __ne__(self, other: Any) -> bool: ...
+    __hash__: ClassVar[None]  # type: ignore[assignment]
     def clear(self) -> None: ...
+    def freeze(self) -> None: ...
+    def is_frozen(self) -> bool: ...
     def indices(self, dim: int) -> RaggedBufferI64: ...
     def flat_indices(self) -> RaggedBufferI64: ...
+    def argsort_lengths(self, descending: bool = False) -> NDArray[np.int64]: ...
+    def sorted_by_length(
+        self, descending: bool = False
+    ) -> Tuple[RaggedBuffer[ScalarType], NDArray[np.int64]]: ...
+    def split(
+        self,
+        sizes: Union[
+            List[int], NDArray[np.int64], List[NDArray[np.int64]], RaggedBufferI64
+        ],
+        dim: int = 0,
+    ) -> List[RaggedBuffer[ScalarType]]: ...
+    def sort_by_feature(
+        self, feature: int, descending: bool = False
+    ) -> RaggedBuffer[ScalarType]: ...
+    def argsort_within(
+        self, feature: int, descending: bool = False
+    ) -> RaggedBufferI64: ...
+    def topk(
+        self, feature: int, k: int, largest: bool = True
+    ) -> RaggedBuffer[ScalarType]: ...
+    @overload
     def padpack(
         self,
+        capacity: Optional[int] = None,
+        strategy: Literal["greedy", "first_fit_decreasing", "best_fit"] = "greedy",
+        sentinel: None = None,
+        always: bool = False,
     ) -> Optional[Tuple[NDArray[np.int64], NDArray[np.float32], NDArray[np.int64]]]: ...
-    def __isub__(self, other: RaggedBuffer[ScalarType]) -> RaggedBuffer[ScalarType]: ...
+    @overload
+    def padpack(
+        self,
+        capacity: Optional[int] = None,
+        strategy: Literal["greedy", "first_fit_decreasing", "best_fit"] = "greedy",
+        *,
+        sentinel: int,
+        always: bool = False,
+    ) -> Optional[Tuple[NDArray[np.int64], NDArray[np.int64], NDArray[np.int64]]]: ...
+    def to_padded(
+        self,
+        max_len: Optional[int] = None,
+        pad_value: Optional[ScalarType] = None,
+        side: Literal["left", "right"] = "right",
+        truncate: Literal["head", "tail"] = "tail",
+    ) -> Tuple[NDArray[ScalarType], NDArray[np.bool_]]: ...
+    def __iadd__(
+        self, other: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float]
+    ) -> RaggedBuffer[ScalarType]: ...
+    def __isub__(
+        self, other: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float]
+    ) -> RaggedBuffer[ScalarType]: ...
+    def __imul__(
+        self, other: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float]
+    ) -> RaggedBuffer[ScalarType]: ...
     def __len__(self) -> int: ...
     def items(self) -> int: ...
     def clone(self) -> RaggedBuffer[ScalarType]: ...
     def materialize(self) -> RaggedBuffer[ScalarType]: ...
+    def isclose(
+        self,
+        other: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float],
+        rtol: float = 1e-5,
+        atol: float = 1e-8,
+        equal_nan: bool = False,
+    ) -> RaggedBufferBool: ...
+    def allclose(
+        self,
+        other: Union[RaggedBuffer[ScalarType], NDArray[ScalarType], int, float],
+        rtol: float = 1e-5,
+        atol: float = 1e-8,
+        equal_nan: bool = False,
+    ) -> bool: ...
 
 RaggedBufferF32 = RaggedBuffer[np.float32]
 RaggedBufferI64 = RaggedBuffer[np.int64]
 RaggedBufferBool = RaggedBuffer[np.bool_]
 
+class SharedRaggedBuffer(Generic[ScalarType]):
+    @classmethod
+    def create(
+        cls, name: str, features: int, max_sequences: int, max_items: int
+    ) -> SharedRaggedBuffer[ScalarType]: ...
+    @classmethod
+    def attach(cls, name: str) -> SharedRaggedBuffer[ScalarType]: ...
+    def push(self, x: NDArray[ScalarType]) -> None: ...
+    def push_empty(self) -> None: ...
+    def reset(self) -> int: ...
+    def generation(self) -> int: ...
+    def name(self) -> str: ...
+    def size0(self) -> int: ...
+    @overload
+    def size1(self) -> NDArray[np.int64]: ...
+    @overload
+    def size1(self, i: int) -> int: ...
+    def size2(self) -> int: ...
+    def items(self) -> int: ...
+    def max_sequences(self) -> int: ...
+    def max_items(self) -> int: ...
+    def is_writable(self) -> bool: ...
+    def as_array(self) -> NDArray[ScalarType]: ...
+    def to_ragged_buffer(self) -> RaggedBuffer[ScalarType]: ...
+    def unlink(self) -> None: ...
+
+SharedRaggedBufferF32 = SharedRaggedBuffer[np.float32]
+SharedRaggedBufferI64 = SharedRaggedBuffer[np.int64]
+SharedRaggedBufferBool = SharedRaggedBuffer[np.bool_]
+
 def cat(
     buffers: List[RaggedBuffer[ScalarType]], dim: int = 0
 ) -> RaggedBuffer[ScalarType]: ...
@@ -69,3 +253,10 @@ def translate_rotate(
     translation: RaggedBuffer[np.float32],
     rotation: RaggedBuffer[np.float32],
 ) -> None: ...
+def padpack_segment_ids(
+    batch: Union[NDArray[np.float32], NDArray[np.int64]], sentinel: int = -1
+) -> NDArray[np.int64]: ...
+def padpack_attention_mask(
+    batch: Union[NDArray[np.float32], NDArray[np.int64]], sentinel: int = -1
+) -> NDArray[np.bool_]: ...
+def inverse_permutation(permutation: NDArray[np.int64]) -> NDArray[np.int64]: ...
@@ -5,7 +5,12 @@ use pyo3::wrap_pyfunction;
 #[cfg(feature = "python")]
 pub mod monomorphs;
 
+mod parallel;
 pub mod ragged_buffer;
+pub mod sampler;
+
+#[cfg(unix)]
+pub mod shared_ragged_buffer;
 
 #[cfg(feature = "python")]
 pub mod ragged_buffer_view;
@@ -17,16 +22,78 @@ fn ragged_buffer(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<monomorphs::RaggedBufferF32>()?;
     m.add_class::<monomorphs::RaggedBufferI64>()?;
     m.add_class::<monomorphs::RaggedBufferBool>()?;
+    #[cfg(unix)]
+    {
+        m.add_class::<monomorphs::SharedRaggedBufferF32>()?;
+        m.add_class::<monomorphs::SharedRaggedBufferI64>()?;
+        m.add_class::<monomorphs::SharedRaggedBufferBool>()?;
+    }
     m.add_function(wrap_pyfunction!(translate_rotate, m)?)?;
+    m.add_function(wrap_pyfunction!(padpack_segment_ids, m)?)?;
+    m.add_function(wrap_pyfunction!(padpack_attention_mask, m)?)?;
+    m.add_function(wrap_pyfunction!(inverse_permutation, m)?)?;
     Ok(())
 }
 
 #[cfg(feature = "python")]
 #[pyfunction]
 fn translate_rotate(
+    py: Python,
     source: &monomorphs::RaggedBufferF32,
     translation: monomorphs::RaggedBufferF32,
     rotation: monomorphs::RaggedBufferF32,
 ) -> PyResult<()> {
-    ragged_buffer_view::translate_rotate(&source.0, &translation.0, &rotation.0)
+    py.allow_threads(|| {
+        ragged_buffer_view::translate_rotate(&source.0, &translation.0, &rotation.0)
+    })
+}
+
+#[cfg(feature = "python")]
+#[pyfunction(sentinel = "-1")]
+fn padpack_segment_ids<'a>(
+    py: Python<'a>,
+    batch: monomorphs::PadpackBatch,
+    sentinel: i64,
+) -> &'a numpy::PyArray2<i64> {
+    let batch = batch.to_ids(sentinel);
+    let segment_ids =
+        py.allow_threads(|| ragged_buffer::padpack_segment_ids(batch.view(), sentinel));
+    numpy::IntoPyArray::into_pyarray(segment_ids, py)
+}
+
+#[cfg(feature = "python")]
+#[pyfunction(sentinel = "-1")]
+fn padpack_attention_mask<'a>(
+    py: Python<'a>,
+    batch: monomorphs::PadpackBatch,
+    sentinel: i64,
+) -> &'a numpy::PyArray3<bool> {
+    let batch = batch.to_ids(sentinel);
+    let mask = py.allow_threads(|| ragged_buffer::padpack_attention_mask(batch.view(), sentinel));
+    numpy::IntoPyArray::into_pyarray(mask, py)
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+fn inverse_permutation<'a>(
+    py: Python<'a>,
+    permutation: numpy::PyReadonlyArray1<i64>,
+) -> PyResult<&'a numpy::PyArray1<i64>> {
+    let permutation = permutation
+        .as_array()
+        .iter()
+        .map(|&i| {
+            usize::try_from(i).map_err(|_| {
+                pyo3::exceptions::PyValueError::new_err(format!(
+                    "Negative index {} is not valid in a permutation",
+                    i
+                ))
+            })
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let inverse = ragged_buffer::inverse_permutation(&permutation)?;
+    Ok(numpy::IntoPyArray::into_pyarray(
+        inverse.into_iter().map(|i| i as i64).collect::<Vec<_>>(),
+        py,
+    ))
 }
@@ -1,7 +1,10 @@
 #![allow(clippy::format_push_string)] // Caused by FromPyObject macro
-use numpy::PyReadonlyArray1;
-use pyo3::types::PySlice;
-use pyo3::{FromPyObject, Py, PyResult};
+use numpy::ndarray::Array2;
+use numpy::{PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::types::{PySlice, PyTuple};
+use pyo3::{exceptions, AsPyPointer, FromPyObject, Py, PyAny, PyObject, PyRef, PyResult, Python};
+
+use crate::ragged_buffer_view::RaggedBufferView;
 
 mod bool;
 mod f32;
@@ -10,13 +13,37 @@ mod i64;
 pub use self::bool::RaggedBufferBool;
 pub use self::f32::RaggedBufferF32;
 pub use self::i64::RaggedBufferI64;
+#[cfg(unix)]
+pub use self::{
+    bool::SharedRaggedBufferBool, f32::SharedRaggedBufferF32, i64::SharedRaggedBufferI64,
+};
 
 #[derive(FromPyObject)]
 pub enum Index<'a> {
     PermutationNP(PyReadonlyArray1<'a, i64>),
-    Permutation(Vec<usize>),
-    Int(usize),
+    MaskNP(PyReadonlyArray1<'a, bool>),
+    Ragged(PyRef<'a, RaggedBufferI64>),
+    // Must come before `Permutation`, since a list of bools can also be extracted as `Vec<i64>`.
+    Mask(Vec<bool>),
+    Permutation(Vec<i64>),
+    Int(isize),
     Slice(Py<PySlice>),
+    Ellipsis(Ellipsis),
+}
+
+/// The `...` object, which stands for as many full slices as needed to index all dimensions.
+#[derive(Debug)]
+pub struct Ellipsis;
+
+impl<'a> FromPyObject<'a> for Ellipsis {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        // The ellipsis is a singleton, so it suffices to compare pointers.
+        if ob.as_ptr() == unsafe { pyo3::ffi::Py_Ellipsis() } {
+            Ok(Ellipsis)
+        } else {
+            Err(exceptions::PyTypeError::new_err("expected Ellipsis"))
+        }
+    }
 }
 
 impl<'a> std::fmt::Debug for Index<'a> {
@@ -26,25 +53,174 @@ impl<'a> std::fmt::Debug for Index<'a> {
                 .debug_tuple("PermutationNP")
                 .field(&arg0.to_vec().unwrap())
                 .finish(),
+            Self::MaskNP(arg0) => f
+                .debug_tuple("MaskNP")
+                .field(&arg0.to_vec().unwrap())
+                .finish(),
+            Self::Ragged(arg0) => f.debug_tuple("Ragged").field(arg0).finish(),
+            Self::Mask(arg0) => f.debug_tuple("Mask").field(arg0).finish(),
             Self::Permutation(arg0) => f.debug_tuple("Permutation").field(arg0).finish(),
             Self::Int(arg0) => f.debug_tuple("Int").field(arg0).finish(),
             Self::Slice(arg0) => f.debug_tuple("Slice").field(arg0).finish(),
+            Self::Ellipsis(arg0) => f.debug_tuple("Ellipsis").field(arg0).finish(),
         }
     }
 }
 
+impl<'a> Index<'a> {
+    /// Index that selects all elements along a dimension.
+    pub fn full(py: pyo3::Python) -> Index<'a> {
+        Index::Slice(PySlice::new(py, 0, isize::MAX, 1).into())
+    }
+}
+
 #[derive(FromPyObject, Debug)]
 pub enum MultiIndex<'a> {
+    Mask(PyRef<'a, RaggedBufferBool>),
+    Tuple(IndexTuple<'a>),
     Index1(Index<'a>),
-    Index2((Index<'a>, Index<'a>)),
-    Index3((Index<'a>, Index<'a>, Index<'a>)),
+}
+
+/// Tuple with one index for each of the leading dimensions, and possibly an ellipsis.
+#[derive(Debug)]
+pub struct IndexTuple<'a>(Vec<Index<'a>>);
+
+impl<'a> FromPyObject<'a> for IndexTuple<'a> {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        let tuple: &PyTuple = ob.downcast()?;
+        Ok(IndexTuple(
+            tuple.iter().map(|i| i.extract()).collect::<PyResult<_>>()?,
+        ))
+    }
+}
+
+impl<'a> IndexTuple<'a> {
+    /// Returns an index for each of the three dimensions, expanding an ellipsis and filling in trailing dimensions with full slices.
+    pub fn expand(self, py: Python) -> PyResult<(Index<'a>, Index<'a>, Index<'a>)> {
+        let IndexTuple(indices) = self;
+        let ellipses = indices
+            .iter()
+            .filter(|i| matches!(i, Index::Ellipsis(_)))
+            .count();
+        if ellipses > 1 {
+            return Err(exceptions::PyIndexError::new_err(
+                "an index can only have a single ellipsis ('...')",
+            ));
+        }
+        let given = indices.len() - ellipses;
+        if given > 3 {
+            return Err(exceptions::PyIndexError::new_err(format!(
+                "too many indices: RaggedBuffer is 3-dimensional, but {} were indexed",
+                given
+            )));
+        }
+        let mut expanded = Vec::with_capacity(3);
+        for index in indices {
+            match index {
+                Index::Ellipsis(_) => expanded.extend((given..3).map(|_| Index::full(py))),
+                index => expanded.push(index),
+            }
+        }
+        while expanded.len() < 3 {
+            expanded.push(Index::full(py));
+        }
+        let mut expanded = expanded.into_iter();
+        Ok((
+            expanded.next().unwrap(),
+            expanded.next().unwrap(),
+            expanded.next().unwrap(),
+        ))
+    }
 }
 
 type PyArray<'a, T, D> = &'a numpy::PyArray<T, numpy::ndarray::Dim<D>>;
 type PadpackResult<'a> = PyResult<
     Option<(
         PyArray<'a, i64, [usize; 2]>,
-        PyArray<'a, f32, [usize; 2]>,
+        PyObject,
         PyArray<'a, i64, [usize; 1]>,
     )>,
 >;
+
+/// Sequence indices returned by `padpack`, either as floats with NaN for padding or as integers with a sentinel.
+#[derive(FromPyObject)]
+pub enum PadpackBatch<'a> {
+    Float(PyReadonlyArray2<'a, f32>),
+    Int(PyReadonlyArray2<'a, i64>),
+}
+
+impl PadpackBatch<'_> {
+    /// Converts the sequence indices into integers with `sentinel` for padding.
+    pub fn to_ids(&self, sentinel: i64) -> Array2<i64> {
+        match self {
+            PadpackBatch::Float(batch) => {
+                batch
+                    .as_array()
+                    .mapv(|b| if b.is_nan() { sentinel } else { b as i64 })
+            }
+            PadpackBatch::Int(batch) => batch.as_array().to_owned(),
+        }
+    }
+}
+
+/// Sizes of the parts for `split` along each dimension.
+pub enum SplitSizes {
+    Sequences(Vec<usize>),
+    // Number of items of each sequence in each part.
+    Items(Vec<Vec<usize>>),
+    // Buffer with one sequence for each sequence of the split buffer, holding the number of items in each part.
+    Lengths(RaggedBufferView<i64>),
+    // Number of items of each sequence in the first of two parts.
+    SplitPoints(Vec<usize>),
+    Features(Vec<usize>),
+}
+
+impl SplitSizes {
+    pub fn extract(sizes: &PyAny, dim: usize) -> PyResult<Self> {
+        match dim {
+            0 => Ok(SplitSizes::Sequences(sizes.extract()?)),
+            1 => {
+                if let Ok(points) = sizes.extract::<PyReadonlyArray1<i64>>() {
+                    Ok(SplitSizes::SplitPoints(to_usizes(
+                        points.as_array().iter(),
+                    )?))
+                } else if let Ok(lengths) = sizes.extract::<PyRef<RaggedBufferI64>>() {
+                    Ok(SplitSizes::Lengths(lengths.0.clone()))
+                } else if let Ok(lengths) = sizes.extract::<Vec<PyReadonlyArray1<i64>>>() {
+                    Ok(SplitSizes::Items(
+                        lengths
+                            .iter()
+                            .map(|l| to_usizes(l.as_array().iter()))
+                            .collect::<PyResult<_>>()?,
+                    ))
+                } else if let Ok(points) = sizes.extract::<Vec<i64>>() {
+                    Ok(SplitSizes::SplitPoints(to_usizes(points.iter())?))
+                } else {
+                    Err(exceptions::PyValueError::new_err(format!(
+                        "Split along dimension 1 expects split points (a 1D array or list of integers), \
+                         a list of 1D arrays with the lengths of each part, or a RaggedBufferI64 of lengths, got {}",
+                        sizes.get_type().name()?
+                    )))
+                }
+            }
+            2 => Ok(SplitSizes::Features(sizes.extract()?)),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "Invalid dimension {}, RaggedBuffer only has 3 dimensions",
+                dim
+            ))),
+        }
+    }
+}
+
+fn to_usizes<'a>(values: impl Iterator<Item = &'a i64>) -> PyResult<Vec<usize>> {
+    values
+        .map(|&i| {
+            usize::try_from(i).map_err(|_| {
+                exceptions::PyValueError::new_err(format!(
+                    "Split sizes must be non-negative, got {}",
+                    i
+                ))
+            })
+        })
+        .collect()
+}
@@ -1,14 +1,21 @@
-use numpy::{PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3, PyReadonlyArrayDyn, ToPyArray};
+use numpy::{
+    IntoPyArray, PyArray1, PyArray2, PyArray3, PyReadonlyArray1, PyReadonlyArray2,
+    PyReadonlyArray3, PyReadonlyArrayDyn, ToPyArray,
+};
 use pyo3::basic::CompareOp;
 use pyo3::prelude::*;
 use pyo3::types::PyType;
 
+use crate::ragged_buffer::{BinOpAssign, RaggedBuffer};
 use crate::ragged_buffer_view::RaggedBufferView;
+use crate::sampler::MinibatchOptions;
+#[cfg(unix)]
+use crate::shared_ragged_buffer::SharedRaggedBuffer;
 
-use super::{Index, MultiIndex, PadpackResult};
+use super::{Index, MultiIndex, PadpackResult, SplitSizes};
 
 #[pyclass]
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct RaggedBufferI64(pub RaggedBufferView<i64>);
 
 #[pymethods]
@@ -18,28 +25,92 @@ impl RaggedBufferI64 {
         RaggedBufferI64(RaggedBufferView::new(features))
     }
     #[classmethod]
-    fn from_array(_cls: &PyType, array: PyReadonlyArray3<i64>) -> Self {
-        RaggedBufferI64(RaggedBufferView::from_array(array))
+    fn from_array(_cls: &PyType, py: Python, array: PyReadonlyArray3<i64>) -> Self {
+        let array = array.as_array();
+        RaggedBufferI64(py.allow_threads(|| RaggedBufferView::from_array(array)))
     }
     #[classmethod]
     fn from_flattened(
         _cls: &PyType,
+        py: Python,
         flattened: PyReadonlyArray2<i64>,
         lengths: PyReadonlyArray1<i64>,
     ) -> PyResult<Self> {
-        Ok(RaggedBufferI64(RaggedBufferView::from_flattened(
-            flattened, lengths,
-        )?))
+        let (flattened, lengths) = (flattened.as_array(), lengths.as_array());
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            RaggedBufferView::from_flattened(flattened, lengths)
+        })?))
+    }
+    #[classmethod]
+    #[args(return_permutation = "false")]
+    fn from_segment_ids(
+        _cls: &PyType,
+        py: Python,
+        flattened: PyReadonlyArray2<i64>,
+        segment_ids: PyReadonlyArray1<i64>,
+        num_segments: usize,
+        return_permutation: bool,
+    ) -> PyResult<PyObject> {
+        let (flattened, segment_ids) = (flattened.as_array(), segment_ids.as_array());
+        let (buffer, permutation) = py.allow_threads(|| {
+            RaggedBufferView::from_segment_ids(flattened, segment_ids, num_segments)
+        })?;
+        let buffer = RaggedBufferI64(buffer).into_py(py);
+        Ok(if return_permutation {
+            (buffer, permutation.into_pyarray(py)).into_py(py)
+        } else {
+            buffer
+        })
+    }
+    #[classmethod]
+    fn from_padded(
+        _cls: &PyType,
+        py: Python,
+        padded: PyReadonlyArray3<i64>,
+        lengths: PyReadonlyArray1<i64>,
+    ) -> PyResult<Self> {
+        let (padded, lengths) = (padded.as_array(), lengths.as_array());
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            RaggedBufferView::from_padded(padded, lengths)
+        })?))
+    }
+    #[classmethod]
+    fn from_padded_mask(
+        _cls: &PyType,
+        py: Python,
+        padded: PyReadonlyArray3<i64>,
+        mask: PyReadonlyArray2<bool>,
+    ) -> PyResult<Self> {
+        let (padded, mask) = (padded.as_array(), mask.as_array());
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            RaggedBufferView::from_padded_mask(padded, mask)
+        })?))
+    }
+    #[classmethod]
+    fn from_padpacked(
+        _cls: &PyType,
+        py: Python,
+        packed: PyReadonlyArray3<i64>,
+        inverse_index: PyReadonlyArray1<i64>,
+        lengths: PyReadonlyArray1<i64>,
+    ) -> PyResult<Self> {
+        let (packed, inverse_index, lengths) = (
+            packed.as_array(),
+            inverse_index.as_array(),
+            lengths.as_array(),
+        );
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            RaggedBufferView::from_padpacked(packed, inverse_index, lengths)
+        })?))
     }
-    fn push(&mut self, items: PyReadonlyArrayDyn<i64>) -> PyResult<()> {
+    fn push(&mut self, py: Python, items: PyReadonlyArrayDyn<i64>) -> PyResult<()> {
         if items.ndim() == 1 && items.len() == 0 {
             self.0.push_empty()
         } else if items.ndim() == 2 {
-            self.0.push(
-                &items
-                    .reshape((items.shape()[0], items.shape()[1]))?
-                    .readonly(),
-            )
+            let items = items.reshape((items.shape()[0], items.shape()[1]))?;
+            let items = items.readonly();
+            let items = items.as_array();
+            py.allow_threads(|| self.0.push(&items))
         } else {
             Err(pyo3::exceptions::PyValueError::new_err(
                 "Expected 2 dimensional array",
@@ -54,62 +125,192 @@ impl RaggedBufferI64 {
         self.0.clear()
     }
 
+    fn freeze(&self, py: Python) -> PyResult<()> {
+        py.allow_threads(|| self.0.freeze())
+    }
+
+    fn is_frozen(&self) -> bool {
+        self.0.is_frozen()
+    }
+
     fn as_array<'a>(
         &self,
         py: Python<'a>,
     ) -> PyResult<&'a numpy::PyArray<i64, numpy::ndarray::Dim<[usize; 2]>>> {
-        self.0.as_array(py)
+        py.allow_threads(|| self.0.contiguous())?.as_array(py)
     }
 
-    fn extend(&mut self, other: &RaggedBufferI64) -> PyResult<()> {
-        self.0.extend(&other.0)
+    fn extend(&mut self, py: Python, other: &RaggedBufferI64) -> PyResult<()> {
+        py.allow_threads(|| self.0.extend(&other.0))
     }
-    fn size0(&self) -> usize {
+    fn size0(&self) -> PyResult<usize> {
         self.0.size0()
     }
-    fn size1(&mut self, py: Python, i: Option<usize>) -> PyResult<PyObject> {
+    fn size1(&self, py: Python, i: Option<usize>) -> PyResult<PyObject> {
         match i {
             Some(i) => self.0.size1(i).map(|s| s.into_py(py)),
             None => self.0.lengths(py).map(|ok| ok.into_py(py)),
         }
     }
-    fn size2(&self) -> usize {
+    fn size2(&self) -> PyResult<usize> {
         self.0.size2()
     }
-    fn indices(&mut self, dim: usize) -> PyResult<RaggedBufferI64> {
-        Ok(RaggedBufferI64(self.0.indices(dim)?))
+    #[args(batch_size = "None", max_items = "None", buckets = "None")]
+    fn minibatches<'a>(
+        &self,
+        py: Python<'a>,
+        seed: u64,
+        batch_size: Option<usize>,
+        max_items: Option<usize>,
+        buckets: Option<usize>,
+    ) -> PyResult<Vec<&'a PyArray1<i64>>> {
+        let options = MinibatchOptions {
+            batch_size,
+            max_items,
+            buckets,
+        };
+        let batches = py.allow_threads(|| self.0.minibatches(seed, &options))?;
+        Ok(batches
+            .into_iter()
+            .map(|batch| batch.into_pyarray(py))
+            .collect())
+    }
+    fn cu_seqlens<'a>(&self, py: Python<'a>) -> PyResult<&'a PyArray1<i64>> {
+        Ok(self.0.cu_seqlens()?.into_pyarray(py))
+    }
+    fn max_seqlen(&self) -> PyResult<usize> {
+        self.0.max_seqlen()
+    }
+    fn segment_ids<'a>(&self, py: Python<'a>) -> PyResult<&'a PyArray1<i64>> {
+        Ok(self.0.segment_ids()?.into_pyarray(py))
+    }
+    fn indices(&self, py: Python, dim: usize) -> PyResult<RaggedBufferI64> {
+        Ok(RaggedBufferI64(py.allow_threads(|| self.0.indices(dim))?))
     }
-    fn flat_indices(&mut self) -> PyResult<RaggedBufferI64> {
-        Ok(RaggedBufferI64(self.0.flat_indices()?))
+    fn flat_indices(&self, py: Python) -> PyResult<RaggedBufferI64> {
+        Ok(RaggedBufferI64(py.allow_threads(|| self.0.flat_indices())?))
+    }
+    #[args(descending = "false")]
+    fn argsort_lengths<'a>(&self, py: Python<'a>, descending: bool) -> PyResult<&'a PyArray1<i64>> {
+        Ok(self.0.argsort_lengths(descending)?.into_pyarray(py))
+    }
+    #[args(descending = "false")]
+    fn sorted_by_length<'a>(
+        &self,
+        py: Python<'a>,
+        descending: bool,
+    ) -> PyResult<(Self, &'a PyArray1<i64>)> {
+        let (sorted, inverse) = py.allow_threads(|| self.0.sorted_by_length(descending))?;
+        Ok((RaggedBufferI64(sorted), inverse.into_pyarray(py)))
+    }
+    #[args(descending = "false")]
+    fn sort_by_feature(&self, py: Python, feature: usize, descending: bool) -> PyResult<Self> {
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            self.0.sort_by_feature(feature, descending)
+        })?))
+    }
+    #[args(descending = "false")]
+    fn argsort_within(
+        &self,
+        py: Python,
+        feature: usize,
+        descending: bool,
+    ) -> PyResult<RaggedBufferI64> {
+        Ok(RaggedBufferI64(py.allow_threads(|| {
+            self.0.argsort_within(feature, descending)
+        })?))
+    }
+    #[args(largest = "true")]
+    fn topk(&self, py: Python, feature: usize, k: usize, largest: bool) -> PyResult<Self> {
+        Ok(RaggedBufferI64(
+            py.allow_threads(|| self.0.topk(feature, k, largest))?,
+        ))
     }
     #[classmethod]
-    fn cat(_cls: &PyType, buffers: Vec<PyRef<RaggedBufferI64>>, dim: usize) -> PyResult<Self> {
-        Ok(RaggedBufferI64(RaggedBufferView::cat(
-            &buffers.iter().map(|b| &b.0).collect::<Vec<_>>(),
-            dim,
-        )?))
+    fn cat(
+        _cls: &PyType,
+        py: Python,
+        buffers: Vec<PyRef<RaggedBufferI64>>,
+        dim: usize,
+    ) -> PyResult<Self> {
+        let buffers = buffers.iter().map(|b| &b.0).collect::<Vec<_>>();
+        Ok(RaggedBufferI64(
+            py.allow_threads(|| RaggedBufferView::cat(&buffers, dim))?,
+        ))
+    }
+    #[args(dim = "0")]
+    fn split(&self, py: Python, sizes: &PyAny, dim: usize) -> PyResult<Vec<Self>> {
+        let sizes = SplitSizes::extract(sizes, dim)?;
+        Ok(py
+            .allow_threads(|| self.0.split(sizes))?
+            .into_iter()
+            .map(RaggedBufferI64)
+            .collect())
     }
-    #[allow(clippy::type_complexity)]
-    fn padpack<'a>(&mut self, py: Python<'a>) -> PadpackResult<'a> {
-        match self.0.padpack()? {
-            Some((padbpack_index, padpack_batch, padpack_inverse_index, dims)) => Ok(Some((
-                padbpack_index.to_pyarray(py).reshape(dims)?,
-                padpack_batch.to_pyarray(py).reshape(dims)?,
-                padpack_inverse_index
-                    .to_pyarray(py)
-                    .reshape(self.0.len()?)?,
-            ))),
+    #[args(
+        capacity = "None",
+        strategy = "\"greedy\"",
+        sentinel = "None",
+        always = "false"
+    )]
+    fn padpack<'a>(
+        &self,
+        py: Python<'a>,
+        capacity: Option<usize>,
+        strategy: &str,
+        sentinel: Option<i64>,
+        always: bool,
+    ) -> PadpackResult<'a> {
+        match py.allow_threads(|| self.0.padpack(capacity, strategy, always))? {
+            Some(padpack) => {
+                let batch = match sentinel {
+                    Some(sentinel) => padpack
+                        .batch_with_sentinel(sentinel)
+                        .to_pyarray(py)
+                        .reshape(padpack.shape)?
+                        .into_py(py),
+                    None => padpack
+                        .batch_float()
+                        .to_pyarray(py)
+                        .reshape(padpack.shape)?
+                        .into_py(py),
+                };
+                Ok(Some((
+                    padpack.index.to_pyarray(py).reshape(padpack.shape)?,
+                    batch,
+                    padpack.inverse_index.into_pyarray(py),
+                )))
+            }
             _ => Ok(None),
         }
     }
-    fn items(&mut self) -> PyResult<usize> {
+    #[args(
+        max_len = "None",
+        pad_value = "None",
+        side = "\"right\"",
+        truncate = "\"tail\""
+    )]
+    fn to_padded<'a>(
+        &self,
+        py: Python<'a>,
+        max_len: Option<usize>,
+        pad_value: Option<i64>,
+        side: &str,
+        truncate: &str,
+    ) -> PyResult<(&'a PyArray3<i64>, &'a PyArray2<bool>)> {
+        let pad_value = pad_value.unwrap_or_default();
+        let (padded, mask) =
+            py.allow_threads(|| self.0.to_padded(max_len, pad_value, side, truncate))?;
+        Ok((padded.into_pyarray(py), mask.into_pyarray(py)))
+    }
+    fn items(&self) -> PyResult<usize> {
         self.0.items()
     }
-    fn clone(&self) -> Self {
-        RaggedBufferI64(self.0.deepclone())
+    fn clone(&self, py: Python) -> PyResult<Self> {
+        Ok(RaggedBufferI64(py.allow_threads(|| self.0.deepclone())?))
     }
-    fn materialize(&self) -> Self {
-        RaggedBufferI64(self.0.materialize())
+    fn materialize(&self, py: Python) -> PyResult<Self> {
+        Ok(RaggedBufferI64(py.allow_threads(|| self.0.materialize())?))
     }
     fn __str__(&self) -> PyResult<String> {
         self.0.__str__()
@@ -119,30 +320,31 @@ impl RaggedBufferI64 {
         self.0.__str__()
     }
 
-    fn __richcmp__(&self, other: RaggedBufferI64, op: CompareOp) -> PyResult<bool> {
+    fn __richcmp__(&self, py: Python, other: RaggedBufferI64, op: CompareOp) -> PyResult<bool> {
         match op {
-            CompareOp::Eq => Ok(self.0 == other.0),
-            CompareOp::Ne => Ok(self.0 != other.0),
+            CompareOp::Eq => py.allow_threads(|| self.0.equals(&other.0)),
+            CompareOp::Ne => py.allow_threads(|| self.0.equals(&other.0)).map(|eq| !eq),
             _ => Err(pyo3::exceptions::PyTypeError::new_err(
                 "Only == and != are supported",
             )),
         }
     }
 
+    // Buffers are mutable and compare by value, so they are unhashable like numpy arrays.
+    fn __hash__(&self) -> PyResult<isize> {
+        Err(pyo3::exceptions::PyTypeError::new_err(
+            "unhashable type: 'RaggedBufferI64'",
+        ))
+    }
+
     // Is substituted for #[cfg(any())] for bool.rs to omit method
     #[cfg(all())]
     fn __add__(
         lhs: PyRef<RaggedBufferI64>,
         rhs: RaggedBufferI64OrI64,
     ) -> PyResult<RaggedBufferI64> {
-        match rhs {
-            RaggedBufferI64OrI64::RB(rhs) => Ok(RaggedBufferI64(
-                lhs.0.binop::<crate::ragged_buffer::BinOpAdd>(&rhs.0)?,
-            )),
-            RaggedBufferI64OrI64::Scalar(rhs) => Ok(RaggedBufferI64(
-                lhs.0.op_scalar::<crate::ragged_buffer::BinOpAdd>(rhs)?,
-            )),
-        }
+        let py = lhs.py();
+        lhs.binop::<crate::ragged_buffer::BinOpAdd>(py, rhs)
     }
 
     #[cfg(all())]
@@ -150,42 +352,299 @@ impl RaggedBufferI64 {
         lhs: PyRef<RaggedBufferI64>,
         rhs: RaggedBufferI64OrI64,
     ) -> PyResult<RaggedBufferI64> {
-        match rhs {
-            RaggedBufferI64OrI64::RB(rhs) => Ok(RaggedBufferI64(
-                lhs.0.binop::<crate::ragged_buffer::BinOpMul>(&rhs.0)?,
-            )),
-            RaggedBufferI64OrI64::Scalar(rhs) => Ok(RaggedBufferI64(
-                lhs.0.op_scalar::<crate::ragged_buffer::BinOpMul>(rhs)?,
-            )),
-        }
+        let py = lhs.py();
+        lhs.binop::<crate::ragged_buffer::BinOpMul>(py, rhs)
     }
 
     #[cfg(all())]
-    fn __isub__(&mut self, rhs: RaggedBufferI64) -> PyResult<()> {
-        self.0.binop_mut::<crate::ragged_buffer::BinOpSub>(&rhs.0)
+    fn __iadd__(&mut self, py: Python, rhs: RaggedBufferI64OrI64) -> PyResult<()> {
+        self.binop_mut::<crate::ragged_buffer::BinOpAdd>(py, rhs)
     }
 
-    fn __getitem__(&self, index: MultiIndex) -> PyResult<RaggedBufferI64> {
+    #[cfg(all())]
+    fn __isub__(&mut self, py: Python, rhs: RaggedBufferI64OrI64) -> PyResult<()> {
+        self.binop_mut::<crate::ragged_buffer::BinOpSub>(py, rhs)
+    }
+
+    #[cfg(all())]
+    fn __imul__(&mut self, py: Python, rhs: RaggedBufferI64OrI64) -> PyResult<()> {
+        self.binop_mut::<crate::ragged_buffer::BinOpMul>(py, rhs)
+    }
+
+    fn __getitem__(&self, py: Python, index: MultiIndex) -> PyResult<PyObject> {
         match index {
-            MultiIndex::Index1(index) => match index {
-                Index::PermutationNP(indices) => Ok(RaggedBufferI64(self.0.swizzle(indices)?)),
-                Index::Permutation(indices) => Ok(RaggedBufferI64(self.0.swizzle_usize(&indices)?)),
-                Index::Int(i) => Ok(RaggedBufferI64(self.0.get_sequence(i)?)),
-                Index::Slice(slice) => panic!("{:?}", slice),
+            MultiIndex::Mask(_) => Err(pyo3::exceptions::PyTypeError::new_err(
+                "Indexing with a boolean mask is only supported in assignments",
+            )),
+            MultiIndex::Index1(index) => {
+                Ok(RaggedBufferI64(self.0.get_item(py, index)?).into_py(py))
+            }
+            MultiIndex::Tuple(indices) => match indices.expand(py)? {
+                // A pair of index arrays gathers individual items into a dense array.
+                (Index::PermutationNP(sequences), Index::PermutationNP(items), i2) => {
+                    let view = self.0.get_slice(py, Index::full(py), Index::full(py), i2)?;
+                    let (sequences, items) = (sequences.as_array(), items.as_array());
+                    let items = py.allow_threads(|| view.gather_pairs(sequences, items))?;
+                    Ok(items.to_pyarray(py).into_py(py))
+                }
+                (i0, i1, i2) => Ok(RaggedBufferI64(self.0.get_items(py, i0, i1, i2)?).into_py(py)),
             },
-            MultiIndex::Index3((i0, i1, i2)) => Ok(RaggedBufferI64(Python::with_gil(|py| {
-                self.0.get_slice(py, i0, i1, i2)
-            })?)),
-            x => panic!("{:?}", x),
         }
     }
+    fn __setitem__(
+        &self,
+        py: Python,
+        index: MultiIndex,
+        value: RaggedBufferI64OrI64,
+    ) -> PyResult<()> {
+        let single_sequence = matches!(index, MultiIndex::Index1(Index::Int(_)));
+        let value = match value {
+            // A 2D array assigned to a single sequence holds the items of the sequence.
+            RaggedBufferI64OrI64::Array(value) if single_sequence && value.ndim() == 2 => {
+                let value = value.as_array();
+                RaggedBuffer::from_dense(value.insert_axis(numpy::ndarray::Axis(0)))?.view()
+            }
+            value => value.into_view()?,
+        };
+        let target = match index {
+            MultiIndex::Mask(mask) => {
+                let mask = &mask.0;
+                return py.allow_threads(|| self.0.assign_masked(mask, &value));
+            }
+            MultiIndex::Index1(i0) => self.0.get_slice(py, i0, Index::full(py), Index::full(py))?,
+            MultiIndex::Tuple(indices) => match indices.expand(py)? {
+                (Index::PermutationNP(_), Index::PermutationNP(_), _) => {
+                    return Err(pyo3::exceptions::PyIndexError::new_err(
+                        "Assigning to items selected by a pair of index arrays is not supported",
+                    ))
+                }
+                (i0, i1, i2) => self.0.get_slice(py, i0, i1, i2)?,
+            },
+        };
+        py.allow_threads(|| target.binop_mut::<BinOpAssign>(&value))
+    }
     fn __len__(&self) -> PyResult<usize> {
         self.0.len()
     }
+
+    #[cfg(all())]
+    #[args(rtol = "1e-5", atol = "1e-8", equal_nan = "false")]
+    fn isclose(
+        &self,
+        py: Python,
+        other: RaggedBufferI64OrI64,
+        rtol: f64,
+        atol: f64,
+        equal_nan: bool,
+    ) -> PyResult<crate::monomorphs::RaggedBufferBool> {
+        let other = other.into_view()?;
+        Ok(crate::monomorphs::RaggedBufferBool(py.allow_threads(
+            || self.0.isclose(&other, rtol, atol, equal_nan),
+        )?))
+    }
+
+    #[cfg(all())]
+    #[args(rtol = "1e-5", atol = "1e-8", equal_nan = "false")]
+    fn allclose(
+        &self,
+        py: Python,
+        other: RaggedBufferI64OrI64,
+        rtol: f64,
+        atol: f64,
+        equal_nan: bool,
+    ) -> PyResult<bool> {
+        let other = other.into_view()?;
+        py.allow_threads(|| self.0.allclose(&other, rtol, atol, equal_nan))
+    }
+}
+
+#[cfg(all())]
+impl RaggedBufferI64 {
+    fn binop<Op: crate::ragged_buffer::BinOp<i64>>(
+        &self,
+        py: Python,
+        rhs: RaggedBufferI64OrI64,
+    ) -> PyResult<RaggedBufferI64> {
+        let lhs = &self.0;
+        match rhs {
+            RaggedBufferI64OrI64::RB(rhs) => {
+                let rhs = &rhs.0;
+                Ok(RaggedBufferI64(py.allow_threads(|| lhs.binop::<Op>(rhs))?))
+            }
+            RaggedBufferI64OrI64::Scalar(rhs) => Ok(RaggedBufferI64(
+                py.allow_threads(|| lhs.op_scalar::<Op>(rhs))?,
+            )),
+            RaggedBufferI64OrI64::Array(rhs) => {
+                let rhs = rhs.as_array();
+                Ok(RaggedBufferI64(
+                    py.allow_threads(|| lhs.binop_dense::<Op>(rhs))?,
+                ))
+            }
+        }
+    }
+
+    fn binop_mut<Op: crate::ragged_buffer::BinOp<i64>>(
+        &mut self,
+        py: Python,
+        rhs: RaggedBufferI64OrI64,
+    ) -> PyResult<()> {
+        let lhs = &self.0;
+        match rhs {
+            RaggedBufferI64OrI64::RB(rhs) => {
+                let rhs = &rhs.0;
+                py.allow_threads(|| lhs.binop_mut::<Op>(rhs))
+            }
+            RaggedBufferI64OrI64::Scalar(rhs) => py.allow_threads(|| lhs.op_scalar_mut::<Op>(rhs)),
+            RaggedBufferI64OrI64::Array(rhs) => {
+                let rhs = rhs.as_array();
+                py.allow_threads(|| lhs.binop_dense_mut::<Op>(rhs))
+            }
+        }
+    }
 }
 
 #[derive(FromPyObject)]
 pub enum RaggedBufferI64OrI64<'p> {
     RB(PyRef<'p, RaggedBufferI64>),
     Scalar(i64),
+    Array(PyReadonlyArrayDyn<'p, i64>),
+}
+
+impl RaggedBufferI64OrI64<'_> {
+    // Converts scalars and dense arrays into buffers that broadcast in the same way.
+    fn into_view(self) -> PyResult<RaggedBufferView<i64>> {
+        Ok(match self {
+            RaggedBufferI64OrI64::RB(value) => value.0.clone(),
+            RaggedBufferI64OrI64::Scalar(value) => RaggedBufferView::from_array(
+                numpy::ndarray::Array3::from_elem((1, 1, 1), value).view(),
+            ),
+            RaggedBufferI64OrI64::Array(value) => {
+                RaggedBuffer::from_dense(value.as_array())?.view()
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+#[pyclass]
+pub struct SharedRaggedBufferI64(pub SharedRaggedBuffer<i64>);
+
+#[cfg(unix)]
+#[pymethods]
+impl SharedRaggedBufferI64 {
+    #[classmethod]
+    fn create(
+        _cls: &PyType,
+        name: &str,
+        features: usize,
+        max_sequences: usize,
+        max_items: usize,
+    ) -> PyResult<Self> {
+        Ok(SharedRaggedBufferI64(SharedRaggedBuffer::create(
+            name,
+            features,
+            max_sequences,
+            max_items,
+        )?))
+    }
+    #[classmethod]
+    fn attach(_cls: &PyType, name: &str) -> PyResult<Self> {
+        Ok(SharedRaggedBufferI64(SharedRaggedBuffer::attach(name)?))
+    }
+    fn push(&mut self, py: Python, items: PyReadonlyArrayDyn<i64>) -> PyResult<()> {
+        if items.ndim() == 1 && items.is_empty() {
+            self.0.push_empty().map_err(Into::into)
+        } else if items.ndim() == 2 {
+            let items = items.reshape((items.shape()[0], items.shape()[1]))?;
+            let items = items.readonly();
+            let items = items.as_array();
+            py.allow_threads(|| self.0.push(&items)).map_err(Into::into)
+        } else {
+            Err(pyo3::exceptions::PyValueError::new_err(
+                "Expected 2 dimensional array",
+            ))
+        }
+    }
+    fn push_empty(&mut self) -> PyResult<()> {
+        self.0.push_empty().map_err(Into::into)
+    }
+    fn reset(&mut self) -> PyResult<u64> {
+        self.0.reset().map_err(Into::into)
+    }
+    fn generation(&self) -> u64 {
+        self.0.generation()
+    }
+    fn name(&self) -> String {
+        self.0.name().to_string()
+    }
+    fn size0(&self) -> usize {
+        self.0.size0()
+    }
+    fn size1(&self, py: Python, i: Option<usize>) -> PyResult<PyObject> {
+        let lengths = self.0.lengths()?;
+        match i {
+            Some(i) => match lengths.get(i) {
+                Some(&len) => Ok(len.into_py(py)),
+                None => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Index {} out of range",
+                    i
+                ))),
+            },
+            None => Ok(lengths.to_pyarray(py).into_py(py)),
+        }
+    }
+    fn size2(&self) -> usize {
+        self.0.features()
+    }
+    fn items(&self) -> usize {
+        self.0.items()
+    }
+    fn max_sequences(&self) -> usize {
+        self.0.max_sequences()
+    }
+    fn max_items(&self) -> usize {
+        self.0.max_items()
+    }
+    fn is_writable(&self) -> bool {
+        self.0.is_writable()
+    }
+    // Returns a read-only array that directly references the shared memory.
+    // Its contents are only valid while `generation()` returns the same value as before the call.
+    // Elements that are not stored as is in shared memory (bool) are copied instead.
+    fn as_array(
+        slf: &PyCell<Self>,
+    ) -> PyResult<&numpy::PyArray<i64, numpy::ndarray::Dim<[usize; 2]>>> {
+        let this = slf.borrow();
+        let features = this.0.features();
+        let array = match unsafe { this.0.data() } {
+            Some(data) => {
+                let view = numpy::ndarray::ArrayView2::from_shape(
+                    (data.len() / features.max(1), features),
+                    data,
+                )
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+                unsafe { numpy::PyArray::borrow_from_array(&view, slf) }
+            }
+            None => {
+                let data = this.0.to_ragged_buffer()?.data;
+                numpy::ndarray::Array2::from_shape_vec(
+                    (data.len() / features.max(1), features),
+                    data,
+                )
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?
+                .to_pyarray(slf.py())
+            }
+        };
+        // Writes through the array would bypass `push` and be overwritten by later pushes.
+        array.call_method1("setflags", (false,))?;
+        Ok(array)
+    }
+    fn to_ragged_buffer(&self, py: Python) -> PyResult<RaggedBufferI64> {
+        Ok(RaggedBufferI64(
+            py.allow_threads(|| self.0.to_ragged_buffer())?.view(),
+        ))
+    }
+    fn unlink(&mut self) -> PyResult<()> {
+        self.0.unlink().map_err(Into::into)
+    }
 }
@@ -0,0 +1,103 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Minimum number of output elements for which kernels are run in parallel.
+/// Smaller buffers are processed serially to avoid the overhead of scheduling work on the thread pool.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+/// Number of elements processed by each task of chunked elementwise kernels.
+pub const CHUNK_SIZE: usize = 1 << 12;
+
+/// Concatenates the items produced by `f(0), f(1), ..., f(n - 1)`.
+///
+/// `elements` is the (estimated) number of items in the result. With the `parallel` feature,
+/// results above `PARALLEL_THRESHOLD` are computed on the rayon thread pool. The output is
+/// identical to the serial path since the order of the concatenation is preserved.
+pub fn flat_map_collect<T, I, F>(n: usize, elements: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    I: Iterator<Item = T>,
+    F: Fn(usize) -> I + Send + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if elements >= PARALLEL_THRESHOLD {
+        return (0..n).into_par_iter().flat_map_iter(f).collect();
+    }
+    let mut result = Vec::with_capacity(elements);
+    for i in 0..n {
+        result.extend(f(i));
+    }
+    result
+}
+
+/// Like `flat_map_collect`, but `f` appends its items to a `Vec` and can fail.
+pub fn try_flat_map_collect<T, E, F>(
+    n: usize,
+    elements: usize,
+    f: F,
+) -> std::result::Result<Vec<T>, E>
+where
+    T: Send,
+    E: Send,
+    F: Fn(usize, &mut Vec<T>) -> std::result::Result<(), E> + Send + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if elements >= PARALLEL_THRESHOLD {
+        let chunks = (0..n)
+            .into_par_iter()
+            .map(|i| {
+                let mut chunk = Vec::new();
+                f(i, &mut chunk).map(|_| chunk)
+            })
+            .collect::<std::result::Result<Vec<_>, E>>()?;
+        let mut result = Vec::with_capacity(elements);
+        for chunk in chunks {
+            result.extend(chunk);
+        }
+        return Ok(result);
+    }
+    let mut result = Vec::with_capacity(elements);
+    for i in 0..n {
+        f(i, &mut result)?;
+    }
+    Ok(result)
+}
+
+/// Splits `data` into consecutive chunks with the given `lengths` and calls `f(i, chunk)` on the `i`th chunk.
+///
+/// With the `parallel` feature, chunks are processed on the rayon thread pool if `data` has at least
+/// `PARALLEL_THRESHOLD` elements.
+pub fn for_each_chunk_mut<T, L, F>(data: &mut [T], lengths: L, f: F)
+where
+    T: Send,
+    L: IntoIterator<Item = usize>,
+    F: Fn(usize, &mut [T]) + Send + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if data.len() >= PARALLEL_THRESHOLD {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+        for len in lengths {
+            let (chunk, tail) = rest.split_at_mut(len);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, chunk)| f(i, chunk));
+        return;
+    }
+    let mut rest = data;
+    for (i, len) in lengths.into_iter().enumerate() {
+        let (chunk, tail) = rest.split_at_mut(len);
+        f(i, chunk);
+        rest = tail;
+    }
+}
+
+/// Lengths of the chunks of at most `CHUNK_SIZE` elements that `len` elements are split into.
+pub fn chunk_lengths(len: usize) -> impl Iterator<Item = usize> {
+    (0..len.div_ceil(CHUNK_SIZE)).map(move |i| usize::min(CHUNK_SIZE, len - i * CHUNK_SIZE))
+}
@@ -1,9 +1,14 @@
 use std::cmp::Ordering;
-use std::collections::{binary_heap, BinaryHeap};
+use std::collections::{binary_heap, BTreeSet, BinaryHeap};
 use std::fmt::{Display, Write};
 use std::ops::{Add, Mul, Range, Sub};
+use std::str::FromStr;
 
-use ndarray::{ArrayView1, ArrayView2, ArrayView3};
+use ndarray::{s, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, ArrayViewD};
+
+use crate::parallel::{
+    chunk_lengths, flat_map_collect, for_each_chunk_mut, try_flat_map_collect, CHUNK_SIZE,
+};
 
 #[derive(Debug)]
 pub enum Error {
@@ -11,7 +16,7 @@ pub enum Error {
 }
 
 impl Error {
-    fn generic<S: Into<String>>(s: S) -> Self {
+    pub(crate) fn generic<S: Into<String>>(s: S) -> Self {
         Self::Generic(s.into())
     }
 }
@@ -58,7 +63,179 @@ impl<T: Mul<T, Output = T>> BinOp<T> for BinOpMul {
     }
 }
 
-impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
+pub struct BinOpAssign;
+
+impl<T> BinOp<T> for BinOpAssign {
+    #[inline]
+    fn op(_lhs: T, rhs: T) -> T {
+        rhs
+    }
+}
+
+/// Side of each sequence on which `RaggedBuffer::to_padded` inserts padding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PadSide {
+    Left,
+    Right,
+}
+
+impl FromStr for PadSide {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "left" => Ok(PadSide::Left),
+            "right" => Ok(PadSide::Right),
+            _ => Err(Error::generic(format!(
+                "Invalid side \"{}\", expected \"left\" or \"right\"",
+                s
+            ))),
+        }
+    }
+}
+
+/// End of each sequence from which `RaggedBuffer::to_padded` removes items that exceed the maximum length.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Truncate {
+    Head,
+    Tail,
+}
+
+impl FromStr for Truncate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "head" => Ok(Truncate::Head),
+            "tail" => Ok(Truncate::Tail),
+            _ => Err(Error::generic(format!(
+                "Invalid truncate \"{}\", expected \"head\" or \"tail\"",
+                s
+            ))),
+        }
+    }
+}
+
+/// Strategy used by `RaggedBuffer::padpack_with` to assign sequences to the rows of the packed batch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PackingStrategy {
+    /// Places each sequence in order into the row with the most free space, or into a new row if it does not fit.
+    #[default]
+    Greedy,
+    /// Places the sequences from longest to shortest into the first row they fit into.
+    /// Slower than `Greedy`, but usually requires fewer rows.
+    FirstFitDecreasing,
+    /// Places each sequence in order into the row with the least free space it fits into.
+    BestFit,
+}
+
+impl FromStr for PackingStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "greedy" => Ok(PackingStrategy::Greedy),
+            "first_fit_decreasing" => Ok(PackingStrategy::FirstFitDecreasing),
+            "best_fit" => Ok(PackingStrategy::BestFit),
+            _ => Err(Error::generic(format!(
+                "Invalid strategy \"{}\", expected \"greedy\", \"first_fit_decreasing\", or \"best_fit\"",
+                s
+            ))),
+        }
+    }
+}
+
+/// Options for `RaggedBuffer::padpack_with`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PadpackOptions {
+    /// Length of each row of the packed batch, defaults to the length of the longest sequence.
+    pub capacity: Option<usize>,
+    pub strategy: PackingStrategy,
+    /// Return a packing even if all sequences have the same length and no `capacity` is given.
+    pub always: bool,
+}
+
+/// Result of `RaggedBuffer::padpack`, which packs multiple sequences into each row of a padded batch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Padpack {
+    /// Index of the item at each position of the packed batch, or 0 for padding.
+    pub index: Vec<i64>,
+    /// Index of the sequence of the item at each position of the packed batch, or -1 for padding.
+    pub batch: Vec<i64>,
+    /// Position of each item in the flattened packed batch.
+    pub inverse_index: Vec<i64>,
+    /// Number of rows and length of each row of the packed batch.
+    pub shape: (usize, usize),
+}
+
+impl Padpack {
+    /// Sequence indices as floats with NaN for padding.
+    pub fn batch_float(&self) -> Vec<f32> {
+        self.batch
+            .iter()
+            .map(|&b| if b < 0 { f32::NAN } else { b as f32 })
+            .collect()
+    }
+
+    /// Sequence indices with `sentinel` for padding.
+    pub fn batch_with_sentinel(&self, sentinel: i64) -> Vec<i64> {
+        self.batch
+            .iter()
+            .map(|&b| if b < 0 { sentinel } else { b })
+            .collect()
+    }
+
+    /// Index of the packed sequence within its row at each position of the packed batch, or -1 for padding.
+    pub fn segment_ids(&self) -> Array2<i64> {
+        padpack_segment_ids(self.batch_view(), -1)
+    }
+
+    /// Block-diagonal attention mask of shape `(rows, max_len, max_len)` that allows each item to attend to the items of the same sequence.
+    pub fn attention_mask(&self) -> Array3<bool> {
+        padpack_attention_mask(self.batch_view(), -1)
+    }
+
+    fn batch_view(&self) -> ArrayView2<'_, i64> {
+        ArrayView2::from_shape(self.shape, &self.batch).unwrap()
+    }
+}
+
+/// Computes the index of each packed sequence within its row from the sequence indices `batch` of a `Padpack`,
+/// where padding is marked by `padding`, which is also used for padding in the result.
+pub fn padpack_segment_ids(batch: ArrayView2<i64>, padding: i64) -> Array2<i64> {
+    let (rows, max_len) = batch.dim();
+    let segment_ids = flat_map_collect(rows, rows * max_len, |row| {
+        let mut segment = -1;
+        let mut previous = None;
+        batch.row(row).into_iter().map(move |&b| {
+            if b == padding {
+                return padding;
+            }
+            if previous != Some(b) {
+                segment += 1;
+                previous = Some(b);
+            }
+            segment
+        })
+    });
+    Array2::from_shape_vec((rows, max_len), segment_ids).unwrap()
+}
+
+/// Computes a block-diagonal attention mask of shape `(rows, max_len, max_len)` from the sequence indices `batch` of a `Padpack`,
+/// where padding is marked by `padding`. Positions `(i, j)` of a row are `true` if both hold items of the same sequence.
+pub fn padpack_attention_mask(batch: ArrayView2<i64>, padding: i64) -> Array3<bool> {
+    let (rows, max_len) = batch.dim();
+    let mask = flat_map_collect(rows, rows * max_len * max_len, |row| {
+        let row = batch.row(row);
+        (0..max_len * max_len).map(move |k| {
+            let (i, j) = (row[k / max_len], row[k % max_len]);
+            i != padding && i == j
+        })
+    });
+    Array3::from_shape_vec((rows, max_len, max_len), mask).unwrap()
+}
+
+impl<T: Copy + Display + std::fmt::Debug + Send + Sync> RaggedBuffer<T> {
     pub fn new(features: usize) -> Self {
         RaggedBuffer {
             data: Vec::new(),
@@ -101,6 +278,185 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
         }
     }
 
+    /// Groups the rows of `data` into `num_segments` sequences, where row `i` is appended to sequence `segment_ids[i]`.
+    /// Rows of the same segment keep their relative order, and segments without rows become empty sequences.
+    pub fn from_segment_ids(
+        data: ArrayView2<T>,
+        segment_ids: ArrayView1<i64>,
+        num_segments: usize,
+    ) -> Result<Self> {
+        Self::from_segment_ids_with_permutation(data, segment_ids, num_segments)
+            .map(|(buffer, _)| buffer)
+    }
+
+    /// Like `from_segment_ids`, but additionally returns the index of the row of `data` at each item of the buffer.
+    pub fn from_segment_ids_with_permutation(
+        data: ArrayView2<T>,
+        segment_ids: ArrayView1<i64>,
+        num_segments: usize,
+    ) -> Result<(Self, Vec<usize>)> {
+        let (items, features) = data.dim();
+        if segment_ids.len() != items {
+            return Err(Error::generic(format!(
+                "Segment ids array has {} items, but data array has {} items",
+                segment_ids.len(),
+                items
+            )));
+        }
+        let mut counts = vec![0; num_segments];
+        for &segment in segment_ids {
+            if segment < 0 || segment as usize >= num_segments {
+                return Err(Error::generic(format!(
+                    "Segment id {} out of range for {} segments",
+                    segment, num_segments
+                )));
+            }
+            counts[segment as usize] += 1;
+        }
+        let mut subarrays = Vec::with_capacity(num_segments);
+        let mut item = 0;
+        for count in counts {
+            subarrays.push(item..item + count);
+            item += count;
+        }
+        let mut offsets = subarrays.iter().map(|r| r.start).collect::<Vec<_>>();
+        let mut permutation = vec![0; items];
+        for (i, &segment) in segment_ids.iter().enumerate() {
+            permutation[offsets[segment as usize]] = i;
+            offsets[segment as usize] += 1;
+        }
+        let data = flat_map_collect(items, items * features, |i| {
+            data.row(permutation[i]).into_iter().cloned()
+        });
+        Ok((
+            RaggedBuffer {
+                data,
+                subarrays,
+                features,
+            },
+            permutation,
+        ))
+    }
+
+    /// Constructs a buffer from a padded array of shape `(size0, max_len, features)`,
+    /// where the `i`th sequence consists of the first `lengths[i]` items of `data[i]`.
+    pub fn from_padded(data: ArrayView3<T>, lengths: ArrayView1<i64>) -> Result<Self> {
+        let (size0, max_len, features) = data.dim();
+        if lengths.len() != size0 {
+            return Err(Error::generic(format!(
+                "Lengths array has {} sequences, but data array has {} sequences",
+                lengths.len(),
+                size0
+            )));
+        }
+        if let Some(len) = lengths
+            .iter()
+            .find(|&&len| len < 0 || len as usize > max_len)
+        {
+            return Err(Error::generic(format!(
+                "Invalid length {} for padded sequences of length {}",
+                len, max_len
+            )));
+        }
+        let mut subarrays = Vec::with_capacity(size0);
+        let mut item = 0;
+        for len in lengths.iter().cloned() {
+            subarrays.push(item..(item + len as usize));
+            item += len as usize;
+        }
+        let data = flat_map_collect(size0, item * features, |i| {
+            data.slice_move(s![i, ..lengths[i] as usize, ..])
+                .into_iter()
+                .cloned()
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features,
+        })
+    }
+
+    /// Constructs a buffer from a padded array of shape `(size0, max_len, features)` and a mask of shape `(size0, max_len)`,
+    /// where the `i`th sequence consists of the items `data[i, j]` for which `mask[i, j]` is `true`.
+    /// The mask does not have to be contiguous, which also allows for padding on the left.
+    pub fn from_padded_mask(data: ArrayView3<T>, mask: ArrayView2<bool>) -> Result<Self> {
+        let (size0, max_len, features) = data.dim();
+        if mask.dim() != (size0, max_len) {
+            return Err(Error::generic(format!(
+                "Mask has shape {:?}, but data array has {} sequences of length {}",
+                mask.shape(),
+                size0,
+                max_len
+            )));
+        }
+        let mut subarrays = Vec::with_capacity(size0);
+        let mut item = 0;
+        for row in mask.outer_iter() {
+            let len = row.iter().filter(|&&m| m).count();
+            subarrays.push(item..(item + len));
+            item += len;
+        }
+        let data = flat_map_collect(size0, item * features, |i| {
+            (0..max_len)
+                .filter(move |&j| mask[[i, j]])
+                .flat_map(move |j| data.slice_move(s![i, j, ..]).into_iter().cloned())
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features,
+        })
+    }
+
+    /// Inverse of `padpack`, which constructs a buffer from a packed array of shape `(rows, max_len, features)`
+    /// using the `inverse_index` of the `Padpack` and the `lengths` of the original sequences.
+    pub fn from_padpacked(
+        packed: ArrayView3<T>,
+        inverse_index: ArrayView1<i64>,
+        lengths: ArrayView1<i64>,
+    ) -> Result<Self> {
+        let (rows, max_len, features) = packed.dim();
+        if let Some(len) = lengths.iter().find(|&&len| len < 0) {
+            return Err(Error::generic(format!("Invalid negative length {}", len)));
+        }
+        let mut subarrays = Vec::with_capacity(lengths.len());
+        let mut item: usize = 0;
+        for len in lengths.iter().cloned() {
+            // Saturates on overflow, which is then reported as a mismatch with the inverse index.
+            let end = item.saturating_add(len as usize);
+            subarrays.push(item..end);
+            item = end;
+        }
+        if item != inverse_index.len() {
+            return Err(Error::generic(format!(
+                "Lengths array specifies {} items, but inverse index has {} items",
+                item,
+                inverse_index.len()
+            )));
+        }
+        if let Some(index) = inverse_index
+            .iter()
+            .find(|&&index| index < 0 || index as usize >= rows * max_len)
+        {
+            return Err(Error::generic(format!(
+                "Inverse index {} out of range for packed array with {} rows of length {}",
+                index, rows, max_len
+            )));
+        }
+        let data = flat_map_collect(item, item * features, |i| {
+            let index = inverse_index[i] as usize;
+            packed
+                .slice_move(s![index / max_len, index % max_len, ..])
+                .into_iter()
+                .cloned()
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features,
+        })
+    }
+
     pub fn extend(&mut self, other: &RaggedBuffer<T>) -> Result<()> {
         if self.features != other.features {
             return Err(Error::generic(format!(
@@ -165,11 +521,12 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
             subarrays.push(item..(item + sublen));
             item += sublen;
         }
-        let mut data = Vec::with_capacity(item * self.features);
-        for i in indices {
-            let Range { start, end } = self.subarrays[*i as usize];
-            data.extend_from_slice(&self.data[start * self.features..end * self.features]);
-        }
+        let data = flat_map_collect(indices.len(), item * self.features, |i| {
+            let Range { start, end } = self.subarrays[indices[i] as usize];
+            self.data[start * self.features..end * self.features]
+                .iter()
+                .copied()
+        });
         Ok(RaggedBuffer {
             data,
             subarrays,
@@ -186,11 +543,12 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
             subarrays.push(item..(item + sublen));
             item += sublen;
         }
-        let mut data = Vec::with_capacity(item * self.features);
-        for i in indices {
-            let Range { start, end } = self.subarrays[*i as usize];
-            data.extend_from_slice(&self.data[start * self.features..end * self.features]);
-        }
+        let data = flat_map_collect(indices.len(), item * self.features, |i| {
+            let Range { start, end } = self.subarrays[indices[i]];
+            self.data[start * self.features..end * self.features]
+                .iter()
+                .copied()
+        });
         Ok(RaggedBuffer {
             data,
             subarrays,
@@ -198,11 +556,27 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
         })
     }
 
+    /// Returns the permutation that orders the sequences by length, see `argsort_lengths`.
+    pub fn argsort_lengths(&self, descending: bool) -> Vec<usize> {
+        let lengths = self.subarrays.iter().map(|r| r.len()).collect::<Vec<_>>();
+        argsort_lengths(&lengths, descending)
+    }
+
+    /// Orders the sequences by length.
+    /// Returns the reordered buffer and the permutation that restores the original order when passed to `swizzle`.
+    pub fn sorted_by_length(&self, descending: bool) -> (RaggedBuffer<T>, Vec<usize>) {
+        let permutation = self.argsort_lengths(descending);
+        let sorted = self
+            .swizzle_usize(&permutation)
+            .expect("permutation of sequence indices");
+        let inverse = inverse_permutation(&permutation).expect("valid permutation");
+        (sorted, inverse)
+    }
+
     pub fn get(&self, i: usize) -> RaggedBuffer<T> {
-        let subarray = self.subarrays[i].clone();
-        let Range { start, end } = subarray;
+        let Range { start, end } = self.subarrays[i];
         RaggedBuffer {
-            subarrays: vec![0..subarray.len()],
+            subarrays: std::iter::once(0..end - start).collect(),
             data: self.data[start * self.features..end * self.features].to_vec(),
             features: self.features,
         }
@@ -231,6 +605,27 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
         self.features
     }
 
+    /// Offsets of the first item of each sequence followed by the total number of items,
+    /// as used by variable-length attention kernels.
+    pub fn cu_seqlens(&self) -> Vec<i64> {
+        std::iter::once(0)
+            .chain(self.subarrays.iter().map(|r| r.end as i64))
+            .collect()
+    }
+
+    pub fn max_seqlen(&self) -> usize {
+        self.subarrays.iter().map(|r| r.len()).max().unwrap_or(0)
+    }
+
+    /// Index of the sequence of each item.
+    pub fn segment_ids(&self) -> Vec<i64> {
+        self.subarrays
+            .iter()
+            .enumerate()
+            .flat_map(|(i, r)| std::iter::repeat_n(i as i64, r.len()))
+            .collect()
+    }
+
     pub fn __str__(&self) -> Result<String> {
         let mut array = String::new();
         array.push_str("RaggedBuffer([");
@@ -271,77 +666,174 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
         Ok(array)
     }
 
+    /// Interprets a dense array as a RaggedBuffer that can be broadcast against other RaggedBuffers in binary operations.
+    /// Arrays of shape `(features,)` become a single sequence with a single item, arrays of shape `(size0, features)`
+    /// have a single item per sequence, and arrays of shape `(size0, size1, features)` are converted with `from_array`.
+    pub fn from_dense(array: ArrayViewD<T>) -> Result<Self> {
+        let shape = array.shape().to_vec();
+        match shape.len() {
+            0 => Ok(RaggedBuffer {
+                data: array.iter().cloned().collect(),
+                subarrays: std::iter::once(0..1).collect(),
+                features: 1,
+            }),
+            1 => Ok(RaggedBuffer {
+                data: array.iter().cloned().collect(),
+                subarrays: std::iter::once(0..1).collect(),
+                features: shape[0],
+            }),
+            2 => Ok(RaggedBuffer {
+                data: array.iter().cloned().collect(),
+                subarrays: (0..shape[0]).map(|i| i..i + 1).collect(),
+                features: shape[1],
+            }),
+            3 => Ok(RaggedBuffer::from_array(
+                array.into_dimensionality().expect("array is 3-dimensional"),
+            )),
+            ndim => Err(Error::generic(format!(
+                "Cannot broadcast {}-dimensional array with RaggedBuffer, expected array of shape (), (features,), (size0, features), or (size0, size1, features)",
+                ndim
+            ))),
+        }
+    }
+
+    /// Applies `Op` elementwise, broadcasting operands with numpy-like rules:
+    /// the number of sequences, the length of each sequence, and the number of features must either be equal
+    /// or 1 for one of the operands.
     pub fn binop<Op: BinOp<T>>(&self, rhs: &RaggedBuffer<T>) -> Result<RaggedBuffer<T>> {
-        if self.features == rhs.features && self.subarrays == rhs.subarrays {
-            let mut data = Vec::with_capacity(self.data.len());
-            for i in 0..self.data.len() {
-                data.push(Op::op(self.data[i], rhs.data[i]));
-            }
-            Ok(RaggedBuffer {
-                data,
-                subarrays: self.subarrays.clone(),
-                features: self.features,
-            })
-        } else if self.features == rhs.features
-            && self.subarrays.len() == rhs.subarrays.len()
-            && rhs.subarrays.iter().all(|r| r.end - r.start == 1)
-        {
-            let mut data = Vec::with_capacity(self.data.len());
-            for (subarray, rhs_subarray) in self.subarrays.iter().zip(rhs.subarrays.iter()) {
-                for item in subarray.clone() {
-                    let lhs_offset = item * self.features;
-                    let rhs_offset = rhs_subarray.start * self.features;
-                    for i in 0..self.features {
-                        data.push(Op::op(self.data[lhs_offset + i], rhs.data[rhs_offset + i]));
-                    }
-                }
-            }
-            Ok(RaggedBuffer {
-                data,
-                subarrays: self.subarrays.clone(),
-                features: self.features,
-            })
-        } else if self.features == rhs.features
-            && self.subarrays.len() == rhs.subarrays.len()
-            && self.subarrays.iter().all(|r| r.end - r.start == 1)
-        {
-            rhs.binop::<Op>(self)
+        let shape = self.broadcast_shape(rhs)?;
+        if self.has_shape(&shape) {
+            let mut result = self.clone();
+            result.apply_binop::<Op, false>(rhs);
+            Ok(result)
+        } else if rhs.has_shape(&shape) {
+            let mut result = rhs.clone();
+            result.apply_binop::<Op, true>(self);
+            Ok(result)
+        } else {
+            let mut result = self.broadcast_to(&shape);
+            result.apply_binop::<Op, false>(rhs);
+            Ok(result)
+        }
+    }
+
+    /// Computes `self = self <op> rhs` in place. `rhs` must be broadcastable to the shape of `self`.
+    pub fn binop_assign<Op: BinOp<T>>(&mut self, rhs: &RaggedBuffer<T>) -> Result<()> {
+        let shape = self.broadcast_shape(rhs)?;
+        if self.has_shape(&shape) {
+            self.apply_binop::<Op, false>(rhs);
+            Ok(())
         } else {
             Err(Error::generic(format!(
-                "Dimensions mismatch: ({}, {:?}, {}) != ({}, {:?}, {})",
+                "Cannot broadcast in place: result has shape ({}, {:?}, {}), but lhs has shape ({}, {:?}, {})",
+                shape.lengths.len(),
+                shape.lengths,
+                shape.features,
                 self.size0(),
-                self.subarrays
-                    .iter()
-                    .map(|r| r.end - r.start)
-                    .collect::<Vec<_>>(),
-                self.size2(),
-                rhs.size0(),
-                rhs.subarrays
-                    .iter()
-                    .map(|r| r.end - r.start)
-                    .collect::<Vec<_>>(),
-                rhs.size2(),
+                self.lengths(),
+                self.features,
             )))
         }
     }
 
-    pub fn op_scalar<Op: BinOp<T>>(&self, scalar: T) -> RaggedBuffer<T> {
+    fn broadcast_shape(&self, rhs: &RaggedBuffer<T>) -> Result<Shape> {
+        let lhs_lengths = self.subarrays.iter().map(|r| r.len()).collect::<Vec<_>>();
+        let rhs_lengths = rhs.subarrays.iter().map(|r| r.len()).collect::<Vec<_>>();
+        broadcast_shape(&lhs_lengths, self.features, &rhs_lengths, rhs.features)
+    }
+
+    fn has_shape(&self, shape: &Shape) -> bool {
+        self.features == shape.features
+            && self.subarrays.len() == shape.lengths.len()
+            && self
+                .subarrays
+                .iter()
+                .zip(shape.lengths.iter())
+                .all(|(r, &len)| r.len() == len)
+    }
+
+    // Returns a copy of `self` expanded to `shape`, which `self` must be broadcastable to.
+    fn broadcast_to(&self, shape: &Shape) -> RaggedBuffer<T> {
+        let mut subarrays = Vec::with_capacity(shape.lengths.len());
+        let mut items = 0;
+        for &len in &shape.lengths {
+            subarrays.push(items..items + len);
+            items += len;
+        }
+        let features = shape.features;
+        let data = flat_map_collect(shape.lengths.len(), items * features, |iseq| {
+            let Range { start, end } = self.subarrays[iseq % self.size0()];
+            let broadcast_items = end - start != shape.lengths[iseq];
+            let broadcast_features = self.features != features;
+            (0..shape.lengths[iseq]).flat_map(move |item| {
+                let offset = (start + if broadcast_items { 0 } else { item }) * self.features;
+                (0..features)
+                    .map(move |i| self.data[offset + if broadcast_features { 0 } else { i }])
+            })
+        });
         RaggedBuffer {
-            data: self.data.iter().map(|x| Op::op(*x, scalar)).collect(),
-            subarrays: self.subarrays.clone(),
-            features: self.features,
+            data,
+            subarrays,
+            features,
+        }
+    }
+
+    // Applies `Op` elementwise to `self.data` and the data of `other`, storing the result in `self.data`.
+    // `other` must be broadcastable to the shape of `self`. If `REVERSE` is set, `other` is the left operand.
+    fn apply_binop<Op: BinOp<T>, const REVERSE: bool>(&mut self, other: &RaggedBuffer<T>) {
+        let features = self.features;
+        if other.subarrays == self.subarrays && other.features == features {
+            let len = self.data.len();
+            for_each_chunk_mut(&mut self.data, chunk_lengths(len), |i, chunk| {
+                let offset = i * CHUNK_SIZE;
+                zip_assign::<T, Op, REVERSE>(chunk, &other.data[offset..offset + chunk.len()]);
+            });
+        } else if features > 0 {
+            let broadcast_features = other.features != features;
+            let lengths = self.subarrays.iter().map(|r| r.len() * features);
+            for_each_chunk_mut(&mut self.data, lengths, |iseq, sequence| {
+                let Range { start, end } = other.subarrays[iseq % other.size0()];
+                let items = &other.data[start * other.features..end * other.features];
+                let broadcast_items = items.len() != sequence.len() / features * other.features;
+                match (broadcast_items, broadcast_features) {
+                    (false, false) => zip_assign::<T, Op, REVERSE>(sequence, items),
+                    (true, false) => {
+                        for chunk in sequence.chunks_exact_mut(features) {
+                            zip_assign::<T, Op, REVERSE>(chunk, items);
+                        }
+                    }
+                    (false, true) => {
+                        for (chunk, &x) in sequence.chunks_exact_mut(features).zip(items) {
+                            scalar_assign::<T, Op, REVERSE>(chunk, x);
+                        }
+                    }
+                    (true, true) => scalar_assign::<T, Op, REVERSE>(sequence, items[0]),
+                }
+            });
         }
     }
 
+    pub fn op_scalar<Op: BinOp<T>>(&self, scalar: T) -> RaggedBuffer<T> {
+        let mut result = self.clone();
+        result.op_scalar_assign::<Op>(scalar);
+        result
+    }
+
+    pub fn op_scalar_assign<Op: BinOp<T>>(&mut self, scalar: T) {
+        let len = self.data.len();
+        for_each_chunk_mut(&mut self.data, chunk_lengths(len), |_, chunk| {
+            for x in chunk.iter_mut() {
+                *x = Op::op(*x, scalar);
+            }
+        });
+    }
+
     pub fn indices(&self, dim: usize) -> Result<RaggedBuffer<i64>> {
         match dim {
             0 => {
-                let mut indices = Vec::with_capacity(self.items());
-                for (index, subarray) in self.subarrays.iter().enumerate() {
-                    for _ in subarray.clone() {
-                        indices.push(index as i64);
-                    }
-                }
+                let indices = flat_map_collect(self.subarrays.len(), self.items(), |index| {
+                    std::iter::repeat_n(index as i64, self.subarrays[index].len())
+                });
                 Ok(RaggedBuffer {
                     subarrays: self.subarrays.clone(),
                     data: indices,
@@ -349,12 +841,9 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
                 })
             }
             1 => {
-                let mut indices = Vec::with_capacity(self.items());
-                for subarray in &self.subarrays {
-                    for (i, _) in subarray.clone().enumerate() {
-                        indices.push(i as i64);
-                    }
-                }
+                let indices = flat_map_collect(self.subarrays.len(), self.items(), |index| {
+                    0..self.subarrays[index].len() as i64
+                });
                 Ok(RaggedBuffer {
                     subarrays: self.subarrays.clone(),
                     data: indices,
@@ -386,10 +875,11 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
                             .join(", ")
                     )));
                 }
-                let mut data = Vec::with_capacity(buffers.iter().map(|b| b.data.len()).sum());
-                for buffer in buffers {
-                    data.extend_from_slice(&buffer.data);
-                }
+                let data = flat_map_collect(
+                    buffers.len(),
+                    buffers.iter().map(|b| b.data.len()).sum(),
+                    |ibuf| buffers[ibuf].data.iter().copied(),
+                );
                 let mut subarrays =
                     Vec::with_capacity(buffers.iter().map(|b| b.subarrays.len()).sum());
                 let mut item = 0;
@@ -437,18 +927,25 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
                             .join(", ")
                     )));
                 }
-                let mut data = Vec::with_capacity(buffers.iter().map(|b| b.data.len()).sum());
+                let data = flat_map_collect(
+                    buffers[0].subarrays.len(),
+                    buffers.iter().map(|b| b.data.len()).sum(),
+                    |i| {
+                        buffers.iter().flat_map(move |buffer| {
+                            let Range { start, end } = buffer.subarrays[i];
+                            buffer.data[start * buffer.features..end * buffer.features]
+                                .iter()
+                                .copied()
+                        })
+                    },
+                );
                 let mut subarrays =
                     Vec::with_capacity(buffers.iter().map(|b| b.subarrays.len()).sum());
                 let mut item = 0;
                 let mut last_item = 0;
                 for i in 0..buffers[0].subarrays.len() {
                     for buffer in buffers {
-                        let Range { start, end } = &buffer.subarrays[i];
-                        data.extend_from_slice(
-                            &buffer.data[start * buffer.features..end * buffer.features],
-                        );
-                        item += end - start;
+                        item += buffer.subarrays[i].len();
                     }
                     subarrays.push(Range {
                         start: last_item,
@@ -479,7 +976,6 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
 
                 let features = buffers.iter().map(|b| b.features).sum();
                 let mut subarrays = Vec::with_capacity(sequences);
-                let mut data = Vec::with_capacity(sequences * features);
                 let mut items = 0;
                 for iseq in 0..sequences {
                     let seqlen = if buffers.iter().any(|b| {
@@ -500,6 +996,9 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
                     };
                     subarrays.push(items..items + seqlen);
                     items += seqlen;
+                }
+                let data = try_flat_map_collect(sequences, items * features, |iseq, data| {
+                    let seqlen = subarrays[iseq].len();
                     for iitem in 0..seqlen {
                         for (ibuf, buffer) in buffers.iter().enumerate() {
                             let _items = buffer.subarrays[iseq].len();
@@ -523,7 +1022,8 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
                             }
                         }
                     }
-                }
+                    Ok(())
+                })?;
 
                 Ok(RaggedBuffer {
                     data,
@@ -538,61 +1038,217 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
         }
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn padpack(&self) -> Option<(Vec<i64>, Vec<f32>, Vec<i64>, (usize, usize))> {
-        if self.subarrays.is_empty()
-            || self
-                .subarrays
-                .iter()
-                .all(|r| r.end - r.start == self.subarrays[0].end - self.subarrays[0].start)
-        {
-            return None;
+    /// Splits the buffer into consecutive chunks of `sizes[i]` sequences, the inverse of `cat` along dimension 0.
+    pub fn split_sequences(&self, sizes: &[usize]) -> Result<Vec<RaggedBuffer<T>>> {
+        let total = sizes.iter().sum::<usize>();
+        if total != self.size0() {
+            return Err(Error::generic(format!(
+                "Split sizes sum to {} sequences, but buffer has {} sequences",
+                total,
+                self.size0()
+            )));
         }
+        let mut sequence = 0;
+        Ok(sizes
+            .iter()
+            .map(|&size| {
+                let subarrays = &self.subarrays[sequence..sequence + size];
+                sequence += size;
+                let start = subarrays.first().map_or(0, |r| r.start);
+                let end = subarrays.last().map_or(0, |r| r.end);
+                RaggedBuffer {
+                    data: self.data[start * self.features..end * self.features].to_vec(),
+                    subarrays: subarrays
+                        .iter()
+                        .map(|r| r.start - start..r.end - start)
+                        .collect(),
+                    features: self.features,
+                }
+            })
+            .collect())
+    }
 
-        let mut padbpack_index = vec![];
-        let mut padpack_batch = vec![];
-        let mut padpack_inverse_index = vec![];
-        let max_seq_len = self
-            .subarrays
+    /// Splits the items of each sequence into consecutive parts, the inverse of `cat` along dimension 1.
+    /// `lengths[p][i]` is the number of items of sequence `i` in part `p`.
+    pub fn split_items(&self, lengths: &[Vec<usize>]) -> Result<Vec<RaggedBuffer<T>>> {
+        if let Some(part) = lengths.iter().find(|l| l.len() != self.size0()) {
+            return Err(Error::generic(format!(
+                "Split lengths have {} sequences, but buffer has {} sequences",
+                part.len(),
+                self.size0()
+            )));
+        }
+        for (i, r) in self.subarrays.iter().enumerate() {
+            let total = lengths.iter().map(|l| l[i]).sum::<usize>();
+            if total != r.len() {
+                return Err(Error::generic(format!(
+                    "Split lengths of sequence {} sum to {} items, but sequence has {} items",
+                    i,
+                    total,
+                    r.len()
+                )));
+            }
+        }
+        let mut offsets = self.subarrays.iter().map(|r| r.start).collect::<Vec<_>>();
+        Ok(lengths
             .iter()
-            .map(|r| r.end - r.start)
-            .max()
-            .unwrap();
-        let mut sequences: BinaryHeap<Sequence> = binary_heap::BinaryHeap::new();
-
-        for (batch_index, subarray) in self.subarrays.iter().enumerate() {
-            let (free, packed_batch_index) = match sequences.peek().cloned() {
-                Some(seq) if seq.free >= subarray.end - subarray.start => {
-                    sequences.pop();
-                    (seq.free, seq.batch_index)
+            .map(|lengths| {
+                let mut subarrays = Vec::with_capacity(lengths.len());
+                let mut item = 0;
+                for &len in lengths {
+                    subarrays.push(item..item + len);
+                    item += len;
                 }
-                _ => {
-                    for _ in 0..max_seq_len {
-                        padbpack_index.push(0);
-                        padpack_batch.push(f32::NAN);
-                    }
-                    (max_seq_len, sequences.len())
+                let data = flat_map_collect(self.size0(), item * self.features, |i| {
+                    let start = offsets[i];
+                    self.data[start * self.features..(start + lengths[i]) * self.features]
+                        .iter()
+                        .copied()
+                });
+                for (offset, &len) in offsets.iter_mut().zip(lengths) {
+                    *offset += len;
                 }
-            };
+                RaggedBuffer {
+                    data,
+                    subarrays,
+                    features: self.features,
+                }
+            })
+            .collect())
+    }
 
+    /// Splits the features into consecutive groups of `sizes[i]` features, the inverse of `cat` along dimension 2.
+    pub fn split_features(&self, sizes: &[usize]) -> Result<Vec<RaggedBuffer<T>>> {
+        let total = sizes.iter().sum::<usize>();
+        if total != self.features {
+            return Err(Error::generic(format!(
+                "Split sizes sum to {} features, but buffer has {} features",
+                total, self.features
+            )));
+        }
+        let mut feature = 0;
+        Ok(sizes
+            .iter()
+            .map(|&size| {
+                let start = feature;
+                feature += size;
+                let data = flat_map_collect(self.items(), self.items() * size, |item| {
+                    let offset = item * self.features + start;
+                    self.data[offset..offset + size].iter().copied()
+                });
+                RaggedBuffer {
+                    data,
+                    subarrays: self.subarrays.clone(),
+                    features: size,
+                }
+            })
+            .collect())
+    }
+
+    pub fn padpack(&self) -> Option<Padpack> {
+        self.padpack_with(&PadpackOptions::default())
+            .expect("default capacity fits all sequences")
+    }
+
+    /// Packs multiple sequences into each row of a padded batch.
+    ///
+    /// Returns `None` if all sequences have the same length, unless `options.always` is set or a capacity is given.
+    pub fn padpack_with(&self, options: &PadpackOptions) -> Result<Option<Padpack>> {
+        let lengths = self.subarrays.iter().map(|r| r.len()).collect::<Vec<_>>();
+        if !options.always
+            && options.capacity.is_none()
+            && lengths.iter().all(|&len| len == lengths[0])
+        {
+            return Ok(None);
+        }
+        let max_seq_len = lengths.iter().cloned().max().unwrap_or(0);
+        let capacity = options.capacity.unwrap_or(max_seq_len);
+        if capacity < max_seq_len {
+            return Err(Error::generic(format!(
+                "Capacity {} is smaller than the longest sequence with {} items",
+                capacity, max_seq_len
+            )));
+        }
+
+        let (rows, placements) = match options.strategy {
+            PackingStrategy::Greedy => pack_greedy(&lengths, capacity),
+            PackingStrategy::FirstFitDecreasing => {
+                let mut order = (0..lengths.len()).collect::<Vec<_>>();
+                order.sort_by_key(|&i| std::cmp::Reverse(lengths[i]));
+                pack_first_fit(&lengths, &order, capacity)
+            }
+            PackingStrategy::BestFit => pack_best_fit(&lengths, capacity),
+        };
+        let mut padpack_index = vec![0; rows * capacity];
+        let mut padpack_batch = vec![-1; rows * capacity];
+        let mut padpack_inverse_index = Vec::with_capacity(self.items());
+        for (batch_index, (subarray, (row, offset))) in
+            self.subarrays.iter().zip(placements).enumerate()
+        {
             for (i, item) in subarray.clone().enumerate() {
-                let packed_index = packed_batch_index * max_seq_len + max_seq_len - free + i;
-                padbpack_index[packed_index] = item as i64;
-                padpack_batch[packed_index] = batch_index as f32;
+                let packed_index = row * capacity + offset + i;
+                padpack_index[packed_index] = item as i64;
+                padpack_batch[packed_index] = batch_index as i64;
                 padpack_inverse_index.push(packed_index as i64);
             }
-            sequences.push(Sequence {
-                batch_index: packed_batch_index,
-                free: free - (subarray.end - subarray.start),
-            });
         }
 
-        Some((
-            padbpack_index,
-            padpack_batch,
-            padpack_inverse_index,
-            (sequences.len(), max_seq_len),
-        ))
+        Ok(Some(Padpack {
+            index: padpack_index,
+            batch: padpack_batch,
+            inverse_index: padpack_inverse_index,
+            shape: (rows, capacity),
+        }))
+    }
+
+    /// Converts the buffer into a dense array of shape `(size0, max_len, features)` and a mask of shape `(size0, max_len)`
+    /// that is `true` for the positions holding items of the buffer.
+    ///
+    /// `max_len` defaults to the length of the longest sequence. Sequences are padded with `pad_value` on the given `side`,
+    /// and longer sequences have items removed from their `truncate` end.
+    pub fn to_padded(
+        &self,
+        max_len: Option<usize>,
+        pad_value: T,
+        side: PadSide,
+        truncate: Truncate,
+    ) -> (Array3<T>, Array2<bool>) {
+        let max_len =
+            max_len.unwrap_or_else(|| self.subarrays.iter().map(|r| r.len()).max().unwrap_or(0));
+        let features = self.features;
+        // Range of retained items and number of padding items before and after them for each sequence.
+        let layout = |i: usize| {
+            let Range { start, end } = self.subarrays[i];
+            let len = (end - start).min(max_len);
+            let items = match truncate {
+                Truncate::Head => end - len..end,
+                Truncate::Tail => start..start + len,
+            };
+            match side {
+                PadSide::Left => (items, max_len - len, 0),
+                PadSide::Right => (items, 0, max_len - len),
+            }
+        };
+        let data = flat_map_collect(self.size0(), self.size0() * max_len * features, |i| {
+            let (items, before, after) = layout(i);
+            std::iter::repeat_n(pad_value, before * features)
+                .chain(
+                    self.data[items.start * features..items.end * features]
+                        .iter()
+                        .cloned(),
+                )
+                .chain(std::iter::repeat_n(pad_value, after * features))
+        });
+        let mask = flat_map_collect(self.size0(), self.size0() * max_len, |i| {
+            let (items, before, after) = layout(i);
+            std::iter::repeat_n(false, before)
+                .chain(std::iter::repeat_n(true, items.len()))
+                .chain(std::iter::repeat_n(false, after))
+        });
+        (
+            Array3::from_shape_vec((self.size0(), max_len, features), data).unwrap(),
+            Array2::from_shape_vec((self.size0(), max_len), mask).unwrap(),
+        )
     }
 
     pub fn items(&self) -> usize {
@@ -608,6 +1264,253 @@ impl<T: Copy + Display + std::fmt::Debug> RaggedBuffer<T> {
     }
 }
 
+impl<T: Copy + Display + std::fmt::Debug + Send + Sync + PartialOrd> RaggedBuffer<T> {
+    /// Sorts the items within each sequence by the value of `feature`.
+    /// The sort is stable and NaN values are treated as larger than all other values.
+    pub fn sort_by_feature(&self, feature: usize, descending: bool) -> Result<RaggedBuffer<T>> {
+        self.select_sorted(feature, descending, None)
+    }
+
+    /// Returns the indices of the items within each sequence that sort the sequence by the value of `feature`.
+    pub fn argsort_within(&self, feature: usize, descending: bool) -> Result<RaggedBuffer<i64>> {
+        self.check_feature(feature)?;
+        let data = flat_map_collect(self.size0(), self.items(), |i| {
+            self.argsort_sequence(i, feature, descending)
+                .into_iter()
+                .map(|j| j as i64)
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays: self.subarrays.clone(),
+            features: 1,
+        })
+    }
+
+    /// Keeps the at most `k` items with the largest (or smallest) values of `feature` in each sequence, ordered by that value.
+    pub fn topk(&self, feature: usize, k: usize, largest: bool) -> Result<RaggedBuffer<T>> {
+        self.select_sorted(feature, largest, Some(k))
+    }
+
+    fn select_sorted(
+        &self,
+        feature: usize,
+        descending: bool,
+        k: Option<usize>,
+    ) -> Result<RaggedBuffer<T>> {
+        self.check_feature(feature)?;
+        let mut subarrays = Vec::with_capacity(self.size0());
+        let mut item = 0;
+        for r in &self.subarrays {
+            let len = k.map_or(r.len(), |k| r.len().min(k));
+            subarrays.push(item..item + len);
+            item += len;
+        }
+        let data = flat_map_collect(self.size0(), item * self.features, |i| {
+            let start = self.subarrays[i].start;
+            let mut order = self.argsort_sequence(i, feature, descending);
+            order.truncate(subarrays[i].len());
+            order.into_iter().flat_map(move |j| {
+                self.data[(start + j) * self.features..(start + j + 1) * self.features]
+                    .iter()
+                    .cloned()
+            })
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features: self.features,
+        })
+    }
+
+    fn argsort_sequence(&self, i: usize, feature: usize, descending: bool) -> Vec<usize> {
+        let Range { start, end } = self.subarrays[i];
+        let key = |j: usize| &self.data[(start + j) * self.features + feature];
+        let mut order = (0..end - start).collect::<Vec<_>>();
+        if descending {
+            order.sort_by(|&a, &b| total_cmp(key(b), key(a)));
+        } else {
+            order.sort_by(|&a, &b| total_cmp(key(a), key(b)));
+        }
+        order
+    }
+
+    fn check_feature(&self, feature: usize) -> Result<()> {
+        if feature >= self.features {
+            return Err(Error::generic(format!(
+                "Feature {} out of range for RaggedBuffer with {} features",
+                feature, self.features
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Orders NaN values after all other values, which makes `PartialOrd` types with NaN totally ordered.
+fn total_cmp<T: PartialOrd>(a: &T, b: &T) -> Ordering {
+    let is_nan = |x: &T| x.partial_cmp(x).is_none();
+    a.partial_cmp(b)
+        .unwrap_or_else(|| is_nan(a).cmp(&is_nan(b)))
+}
+
+/// Computes the shape of the result of a broadcasting binary operation on operands with the given sequence lengths and features.
+pub(crate) fn broadcast_shape(
+    lhs_lengths: &[usize],
+    lhs_features: usize,
+    rhs_lengths: &[usize],
+    rhs_features: usize,
+) -> Result<Shape> {
+    let features = match (lhs_features, rhs_features) {
+        (l, r) if l == r => l,
+        (1, r) => r,
+        (l, 1) => l,
+        (l, r) => {
+            return Err(Error::generic(format!(
+                "Cannot broadcast features: lhs has {} features, but rhs has {} features",
+                l, r
+            )))
+        }
+    };
+    let size0 = match (lhs_lengths.len(), rhs_lengths.len()) {
+        (l, r) if l == r => l,
+        (1, r) => r,
+        (l, 1) => l,
+        (l, r) => {
+            return Err(Error::generic(format!(
+                "Cannot broadcast sequences: lhs has {} sequences, but rhs has {} sequences",
+                l, r
+            )))
+        }
+    };
+    let mut lengths = Vec::with_capacity(size0);
+    for iseq in 0..size0 {
+        let l = lhs_lengths[iseq % lhs_lengths.len()];
+        let r = rhs_lengths[iseq % rhs_lengths.len()];
+        lengths.push(match (l, r) {
+            (l, r) if l == r => l,
+            (1, r) => r,
+            (l, 1) => l,
+            (l, r) => {
+                return Err(Error::generic(format!(
+                    "Cannot broadcast sequence {}: lhs has {} items, but rhs has {} items",
+                    iseq, l, r
+                )))
+            }
+        });
+    }
+    Ok(Shape { lengths, features })
+}
+
+// Shape of a RaggedBuffer, given by the length of each sequence and the number of features.
+pub(crate) struct Shape {
+    pub lengths: Vec<usize>,
+    pub features: usize,
+}
+
+#[inline(always)]
+fn zip_assign<T: Copy, Op: BinOp<T>, const REVERSE: bool>(dst: &mut [T], src: &[T]) {
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = if REVERSE {
+            Op::op(s, *d)
+        } else {
+            Op::op(*d, s)
+        };
+    }
+}
+
+#[inline(always)]
+fn scalar_assign<T: Copy, Op: BinOp<T>, const REVERSE: bool>(dst: &mut [T], scalar: T) {
+    for d in dst.iter_mut() {
+        *d = if REVERSE {
+            Op::op(scalar, *d)
+        } else {
+            Op::op(*d, scalar)
+        };
+    }
+}
+
+// Each of the packing functions returns the number of rows and the row and offset of each sequence.
+
+fn pack_greedy(lengths: &[usize], capacity: usize) -> (usize, Vec<(usize, usize)>) {
+    let mut sequences: BinaryHeap<Sequence> = binary_heap::BinaryHeap::new();
+    let placements = lengths
+        .iter()
+        .map(|&len| {
+            let seq = match sequences.peek().cloned() {
+                Some(seq) if seq.free >= len => {
+                    sequences.pop();
+                    seq
+                }
+                _ => Sequence {
+                    free: capacity,
+                    batch_index: sequences.len(),
+                },
+            };
+            sequences.push(Sequence {
+                free: seq.free - len,
+                batch_index: seq.batch_index,
+            });
+            (seq.batch_index, capacity - seq.free)
+        })
+        .collect();
+    (sequences.len(), placements)
+}
+
+fn pack_first_fit(
+    lengths: &[usize],
+    order: &[usize],
+    capacity: usize,
+) -> (usize, Vec<(usize, usize)>) {
+    // Segment tree over the free space of up to one row per sequence, where each node holds the maximum of its children.
+    // Unused rows have full capacity, so the leftmost row with enough space is either in use or the next new row.
+    let size = lengths.len().next_power_of_two();
+    let mut free = vec![capacity; 2 * size];
+    let mut placements = vec![(0, 0); lengths.len()];
+    let mut rows = 0;
+    for &i in order {
+        let mut node = 1;
+        while node < size {
+            node = if free[2 * node] >= lengths[i] {
+                2 * node
+            } else {
+                2 * node + 1
+            };
+        }
+        let row = node - size;
+        placements[i] = (row, capacity - free[node]);
+        rows = rows.max(row + 1);
+        free[node] -= lengths[i];
+        while node > 1 {
+            node /= 2;
+            free[node] = free[2 * node].max(free[2 * node + 1]);
+        }
+    }
+    (rows, placements)
+}
+
+fn pack_best_fit(lengths: &[usize], capacity: usize) -> (usize, Vec<(usize, usize)>) {
+    // Rows ordered by free space and index.
+    let mut rows = BTreeSet::new();
+    let mut count = 0;
+    let placements = lengths
+        .iter()
+        .map(|&len| {
+            let (free, row) = match rows.range((len, 0)..).next().cloned() {
+                Some(row) => {
+                    rows.remove(&row);
+                    row
+                }
+                None => {
+                    count += 1;
+                    (capacity, count - 1)
+                }
+            };
+            rows.insert((free - len, row));
+            (row, capacity - free)
+        })
+        .collect();
+    (count, placements)
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 struct Sequence {
     free: usize,
@@ -627,3 +1530,32 @@ impl PartialOrd for Sequence {
         Some(self.cmp(other))
     }
 }
+
+/// Returns the permutation that orders sequences with the given `lengths` from shortest to longest, or longest to shortest if `descending` is set.
+/// Sequences of the same length keep their relative order.
+pub fn argsort_lengths(lengths: &[usize], descending: bool) -> Vec<usize> {
+    let mut permutation = (0..lengths.len()).collect::<Vec<_>>();
+    if descending {
+        permutation.sort_by_key(|&i| std::cmp::Reverse(lengths[i]));
+    } else {
+        permutation.sort_by_key(|&i| lengths[i]);
+    }
+    permutation
+}
+
+/// Returns the permutation that undoes `permutation`, so that `inverse[permutation[i]] == i`.
+pub fn inverse_permutation(permutation: &[usize]) -> Result<Vec<usize>> {
+    let mut inverse = vec![usize::MAX; permutation.len()];
+    for (i, &p) in permutation.iter().enumerate() {
+        if p >= permutation.len() || inverse[p] != usize::MAX {
+            return Err(Error::generic(format!(
+                "Index {} at position {} is out of range or repeated, which is not a valid permutation of length {}",
+                p,
+                i,
+                permutation.len()
+            )));
+        }
+        inverse[p] = i;
+    }
+    Ok(inverse)
+}
@@ -1,11 +1,19 @@
 use std::fmt::Display;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use numpy::{PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3, ToPyArray};
+use ndarray::{Array2, Array3, ArrayView1, ArrayView2, ArrayView3, ArrayViewD};
+use numpy::ToPyArray;
 use pyo3::{exceptions, PyErr, PyResult, Python};
 
-use crate::monomorphs::Index;
-use crate::ragged_buffer::{BinOp, Error, RaggedBuffer};
+use crate::monomorphs::{Index, SplitSizes};
+use crate::parallel::{flat_map_collect, try_flat_map_collect};
+use crate::ragged_buffer::{
+    argsort_lengths, broadcast_shape, inverse_permutation, BinOp, Error, PackingStrategy, PadSide,
+    Padpack, PadpackOptions, RaggedBuffer, Truncate,
+};
+use crate::sampler::{minibatches, MinibatchOptions};
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 enum Slice {
@@ -15,6 +23,13 @@ enum Slice {
         step: usize,
     },
     Permutation(Vec<usize>),
+    // Python slice along the ragged dimension that is resolved separately for each sequence,
+    // since negative bounds and steps are relative to the length of the sequence.
+    Relative {
+        start: Option<isize>,
+        stop: Option<isize>,
+        step: isize,
+    },
 }
 
 impl Slice {
@@ -22,123 +37,571 @@ impl Slice {
         match self {
             Slice::Range { start, end, step } => Box::new((start..end).step_by(step)),
             Slice::Permutation(permutation) => Box::new(permutation.into_iter()),
+            Slice::Relative { .. } => unreachable!("relative slice outside of ragged dimension"),
         }
     }
 
+    fn iter(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        match self {
+            Slice::Range { start, end, step } => Box::new((*start..*end).step_by(*step)),
+            Slice::Permutation(permutation) => Box::new(permutation.iter().copied()),
+            Slice::Relative { .. } => unreachable!("relative slice outside of ragged dimension"),
+        }
+    }
+
+    // Number of selected positions. For relative slices, this is an upper bound that holds for sequences of any length.
     fn len(&self) -> usize {
         match self {
-            Slice::Range { start, end, step } => (end - start + step - 1) / step,
+            Slice::Range { start, end, step } => (end - start).div_ceil(*step),
             Slice::Permutation(permutation) => permutation.len(),
+            Slice::Relative { .. } => isize::MAX as usize,
+        }
+    }
+
+    // Indices selected in a sequence with `n` items along the ragged dimension.
+    fn ragged_items(&self, n: usize) -> Box<dyn Iterator<Item = usize> + '_> {
+        match self {
+            &Slice::Relative { start, stop, step } => {
+                let (start, len) = resolve_slice(start, stop, step, n);
+                Box::new((0..len).map(move |k| (start + k as isize * step) as usize))
+            }
+            _ => Box::new(self.iter().take_while(move |&i| i < n)),
+        }
+    }
+
+    // Index selected at position `k` in a sequence with `n` items along the ragged dimension.
+    fn ragged_at(&self, k: usize, n: usize) -> usize {
+        match self {
+            &Slice::Relative { start, stop, step } => {
+                let (start, _) = resolve_slice(start, stop, step, n);
+                (start + k as isize * step) as usize
+            }
+            _ => self.at(k),
+        }
+    }
+
+    // Number of items selected in a sequence with `n` items along the ragged dimension.
+    fn ragged_len(&self, n: usize) -> usize {
+        match self {
+            &Slice::Range { start, end, step } => {
+                let end = end.min(n);
+                if end > start {
+                    (end - start).div_ceil(step)
+                } else {
+                    0
+                }
+            }
+            Slice::Permutation(permutation) => permutation
+                .iter()
+                .position(|&i| i >= n)
+                .unwrap_or(permutation.len()),
+            &Slice::Relative { start, stop, step } => resolve_slice(start, stop, step, n).1,
+        }
+    }
+
+    // Index selected at position `k`.
+    fn at(&self, k: usize) -> usize {
+        match self {
+            Slice::Range { start, step, .. } => start + k * step,
+            Slice::Permutation(permutation) => permutation[k],
+            Slice::Relative { .. } => unreachable!("relative slice outside of ragged dimension"),
+        }
+    }
+
+    // Whether `index` selects all positions of `self` in order.
+    fn is_identity(&self, index: &Slice) -> bool {
+        matches!(index, &Slice::Range { start: 0, end, step: 1 } if end >= self.len())
+    }
+
+    // Selects the positions `index` of `self` along a dense dimension.
+    // Returns the first out of bounds position on failure.
+    fn compose(&self, index: &Slice) -> std::result::Result<Slice, usize> {
+        if self.is_identity(index) {
+            return Ok(self.clone());
+        }
+        let len = self.len();
+        match (self, index) {
+            (
+                &Slice::Range { start, step, .. },
+                &Slice::Range {
+                    start: istart,
+                    end: iend,
+                    step: istep,
+                },
+            ) => {
+                let iend = iend.min(len);
+                Ok(if istart >= iend {
+                    Slice::Range {
+                        start,
+                        end: start,
+                        step: 1,
+                    }
+                } else {
+                    Slice::Range {
+                        start: start + istart * step,
+                        end: start + (iend - 1) * step + 1,
+                        step: step * istep,
+                    }
+                })
+            }
+            _ => index
+                .iter()
+                .map(|k| if k < len { Ok(self.at(k)) } else { Err(k) })
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map(Slice::Permutation),
+        }
+    }
+
+    // Selects the positions `index` of `self` along the ragged dimension.
+    // Along this dimension, a sequence with `n` items selects all indices of the slice up to the first index that is `>= n`.
+    // Positions past the end of `self` are mapped to `usize::MAX`, which ends the selection in every sequence.
+    // Returns `None` if the composition cannot be represented as a single slice, which is the case for unsorted permutations
+    // and most relative slices.
+    fn compose_ragged(&self, index: &Slice) -> Option<Slice> {
+        if self.is_identity(index) {
+            return Some(self.clone());
+        }
+        let len = self.len();
+        match (self, index) {
+            (Slice::Relative { .. }, _) | (_, Slice::Relative { .. }) => None,
+            (Slice::Range { .. }, Slice::Range { .. }) => self.compose(index).ok(),
+            (Slice::Permutation(permutation), _) if permutation.windows(2).any(|w| w[0] > w[1]) => {
+                None
+            }
+            _ => Some(Slice::Permutation(
+                index
+                    .iter()
+                    .map(|k| if k < len { self.at(k) } else { usize::MAX })
+                    .collect(),
+            )),
         }
     }
 }
 
-// TODO: Eq/PartialEq/Hash
+// A view resolved against the buffer it refers to.
+struct ResolvedView<'a, T> {
+    inner: &'a RaggedBuffer<T>,
+    // Index of each selected sequence in `inner`.
+    sequences: Vec<usize>,
+    // Number of selected items in each selected sequence.
+    lengths: Vec<usize>,
+    items: Slice,
+    // Index of each selected feature in `inner`.
+    features: Vec<usize>,
+}
+
+impl<'a, T: Copy> ResolvedView<'a, T> {
+    fn new(inner: &'a RaggedBuffer<T>, view: (Slice, Slice, Slice)) -> Self {
+        let (v0, v1, v2) = view;
+        let sequences = v0.into_iter().collect::<Vec<_>>();
+        let features = v2.into_iter().collect::<Vec<_>>();
+        let lengths = sequences
+            .iter()
+            .map(|&i0| v1.ragged_len(inner.subarrays[i0].len()))
+            .collect();
+        ResolvedView {
+            inner,
+            sequences,
+            lengths,
+            items: v1,
+            features,
+        }
+    }
+
+    // Indices into `inner` of the selected items of the `i`th selected sequence.
+    fn items_of(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let Range { start, end } = self.inner.subarrays[self.sequences[i]];
+        self.items
+            .ragged_items(end - start)
+            .map(move |i1| start + i1)
+    }
+
+    fn get(&self, item: usize, feature: usize) -> T {
+        self.inner.data[item * self.inner.features + self.features[feature]]
+    }
+
+    // Element at position (`iseq`, `item`, `feature`) of the view broadcast to a larger shape.
+    fn broadcast_get(&self, iseq: usize, item: usize, feature: usize) -> T {
+        let i = iseq % self.sequences.len();
+        let item = if self.lengths[i] == 1 { 0 } else { item };
+        let feature = if self.features.len() == 1 { 0 } else { feature };
+        self.get(self.item(i, item), feature)
+    }
+
+    // Index into `inner` of the `k`th selected item of the `i`th selected sequence.
+    fn item(&self, i: usize, k: usize) -> usize {
+        let Range { start, end } = self.inner.subarrays[self.sequences[i]];
+        start + self.items.ragged_at(k, end - start)
+    }
+}
+
+// Storage shared by a buffer and all views of it.
+#[derive(Debug)]
+pub struct Storage<T> {
+    buffer: RwLock<RaggedBuffer<T>>,
+    // Incremented by every operation that changes the shape of the buffer, which invalidates existing views.
+    generation: AtomicU64,
+    // Set by `freeze`, after which the buffer can no longer be modified.
+    frozen: AtomicBool,
+}
+
+impl<T> Storage<T> {
+    fn new(buffer: RaggedBuffer<T>) -> Arc<Self> {
+        Arc::new(Storage {
+            buffer: RwLock::new(buffer),
+            generation: AtomicU64::new(0),
+            frozen: AtomicBool::new(false),
+        })
+    }
+}
+
+type ReadGuard<'a, T> = RwLockReadGuard<'a, RaggedBuffer<T>>;
+type WriteGuard<'a, T> = RwLockWriteGuard<'a, RaggedBuffer<T>>;
+
+/// A RaggedBuffer or a view of a RaggedBuffer.
+///
+/// Views share storage with the buffer they were created from, and in-place operations on either are visible through both.
+/// Operations that change the shape of a buffer (`push`, `extend`, `clear`) copy the view into new storage when called on a view,
+/// and invalidate all existing views when called on the buffer itself. Accessing an invalidated view raises an error.
 #[derive(Clone, Debug)]
 pub struct RaggedBufferView<T> {
-    pub inner: Arc<RwLock<RaggedBuffer<T>>>,
+    pub inner: Arc<Storage<T>>,
     view: Option<(Slice, Slice, Slice)>,
+    // Generation of the storage at the time the view was created. Unused if `view` is `None`.
+    generation: u64,
 }
 
-impl<T: numpy::Element + Copy + Display + std::fmt::Debug> RaggedBufferView<T> {
+impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Sync> RaggedBufferView<T> {
     pub fn new(features: usize) -> Self {
-        RaggedBufferView {
-            inner: Arc::new(RwLock::new(RaggedBuffer::new(features))),
-            view: None,
+        RaggedBuffer::new(features).view()
+    }
+
+    /// Selects `i0`, `i1`, `i2` along the three dimensions of `self`.
+    /// The result is a view over the same storage as `self`, which is only copied once the view is materialized.
+    pub fn get_slice(
+        &self,
+        py: Python,
+        i0: Index,
+        i1: Index,
+        i2: Index,
+    ) -> PyResult<RaggedBufferView<T>> {
+        let (v0, v1, v2) = self.slices()?;
+        let s0 = to_slice(py, i0, 0, v0.len())?;
+        let s1 = to_slice(py, i1, 1, v1.len())?;
+        let s2 = to_slice(py, i2, 2, v2.len())?;
+        self.slice(s0, s1, s2)
+    }
+
+    /// Indexes the first dimension of `self` with a single index.
+    /// Integers and index arrays return a copy of the selected sequences, while slices return a view.
+    pub fn get_item(&self, py: Python, index: Index) -> PyResult<RaggedBufferView<T>> {
+        match index {
+            Index::Slice(_) | Index::Ellipsis(_) => {
+                self.get_slice(py, index, Index::full(py), Index::full(py))
+            }
+            index => {
+                let sequences = to_slice(py, index, 0, self.size0()?)?
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                py.allow_threads(|| self.swizzle_usize(&sequences))
+            }
         }
     }
 
-    pub fn get_slice<'a>(
+    /// Like `get_slice`, but additionally supports a `RaggedBufferI64` along the ragged dimension,
+    /// which selects the items with the given indices in each sequence and returns a copy.
+    pub fn get_items(
         &self,
-        py: Python<'a>,
+        py: Python,
         i0: Index,
         i1: Index,
         i2: Index,
     ) -> PyResult<RaggedBufferView<T>> {
-        // TODO: Check that i0, i1, i2 are valid indices
-        let materialized = self.materialize();
-        let v0 = match i0 {
-            Index::PermutationNP(np) => {
-                Slice::Permutation(np.to_vec()?.into_iter().map(|x| x as usize).collect())
+        match i1 {
+            Index::Ragged(indices) => {
+                let indices = indices.0.clone();
+                let view = self.get_slice(py, i0, Index::full(py), i2)?;
+                py.allow_threads(|| view.gather(&indices))
             }
-            Index::Permutation(p) => Slice::Permutation(p),
-            Index::Int(i) => Slice::Range {
-                start: i,
-                end: i + 1,
-                step: 1,
-            },
-            Index::Slice(slice) => {
-                let indices = slice
-                    .as_ref(py)
-                    .indices(materialized.size0().try_into().unwrap())?;
-                Slice::Range {
-                    start: indices.start as usize,
-                    end: indices.stop as usize,
-                    step: indices.step as usize,
-                }
+            i1 => self.get_slice(py, i0, i1, i2),
+        }
+    }
+
+    /// Selects the items `indices[i]` from the `i`th sequence of `self`.
+    /// `indices` must have one sequence for each sequence of `self` and a single feature.
+    /// Negative indices count from the end of each sequence.
+    pub fn gather(&self, indices: &RaggedBufferView<i64>) -> PyResult<RaggedBufferView<T>> {
+        // Indices are copied out first so that no two locks of the same storage are held at once.
+        let (indices, lengths) = {
+            let guard = indices.get()?;
+            let indices = ResolvedView::new(&guard, indices.slices_of(&guard));
+            if indices.features.len() != 1 {
+                return Err(exceptions::PyIndexError::new_err(format!(
+                    "item indices must have a single feature, but have {}",
+                    indices.features.len()
+                )));
             }
+            let values = (0..indices.sequences.len())
+                .flat_map(|iseq| indices.items_of(iseq))
+                .map(|i| indices.get(i, 0))
+                .collect::<Vec<_>>();
+            (values, indices.lengths)
         };
-        let v1 = match i1 {
-            Index::PermutationNP(np) => {
-                Slice::Permutation(np.to_vec()?.into_iter().map(|x| x as usize).collect())
+        let guard = self.get()?;
+        let source = ResolvedView::new(&guard, self.slices_of(&guard));
+        if lengths.len() != source.sequences.len() {
+            return Err(exceptions::PyIndexError::new_err(format!(
+                "item indices have {} sequences, but buffer has {} sequences",
+                lengths.len(),
+                source.sequences.len()
+            )));
+        }
+        let features = source.features.len();
+        let mut subarrays = Vec::with_capacity(lengths.len());
+        let mut item = 0;
+        for &len in &lengths {
+            subarrays.push(item..item + len);
+            item += len;
+        }
+        let (source, indices, subarrays_ref) = (&source, &indices, &subarrays);
+        let data = try_flat_map_collect(source.sequences.len(), item * features, |iseq, data| {
+            let len = source.lengths[iseq];
+            for &i in &indices[subarrays_ref[iseq].clone()] {
+                let k = to_item_index(i, iseq, len)?;
+                let item = source.item(iseq, k);
+                data.extend((0..features).map(|i2| source.get(item, i2)));
             }
-            Index::Permutation(p) => Slice::Permutation(p),
-            Index::Int(i) => Slice::Range {
-                start: i,
-                end: i + 1,
+            Ok::<_, PyErr>(())
+        })?;
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features,
+        }
+        .view())
+    }
+
+    /// Gathers the items at positions `(sequences[k], items[k])` of `self` into a dense array with one row for each `k`.
+    /// Negative indices count from the end of the buffer and sequence respectively.
+    pub fn gather_pairs(
+        &self,
+        sequences: ArrayView1<i64>,
+        items: ArrayView1<i64>,
+    ) -> PyResult<Array2<T>> {
+        if sequences.len() != items.len() {
+            return Err(exceptions::PyIndexError::new_err(format!(
+                "shape mismatch: got {} sequence indices and {} item indices",
+                sequences.len(),
+                items.len()
+            )));
+        }
+        let guard = self.get()?;
+        let source = ResolvedView::new(&guard, self.slices_of(&guard));
+        let features = source.features.len();
+        let mut data = Vec::with_capacity(sequences.len() * features);
+        for (&iseq, &k) in sequences.iter().zip(items.iter()) {
+            let iseq = to_index(iseq as isize, 0, source.sequences.len())?;
+            let k = to_item_index(k, iseq, source.lengths[iseq])?;
+            let item = source.item(iseq, k);
+            data.extend((0..features).map(|i2| source.get(item, i2)));
+        }
+        Ok(Array2::from_shape_vec((sequences.len(), features), data).unwrap())
+    }
+
+    // Composes the view of `self` with selections of positions along each dimension of the view.
+    fn slice(&self, s0: Slice, s1: Slice, s2: Slice) -> PyResult<RaggedBufferView<T>> {
+        // The slices and generation are read under the same lock, so that the new view
+        // cannot refer to a shape of the storage that was replaced by a concurrent resize.
+        let (v0, v1, v2, generation) = {
+            let inner = self.get()?;
+            self.check_generation()?;
+            let (v0, v1, v2) = self.slices_of(&inner);
+            let v1 = match (&v1, &s1) {
+                // Relative slices can only be applied directly to views that select all items of every sequence.
+                (
+                    &Slice::Range {
+                        start: 0,
+                        end,
+                        step: 1,
+                    },
+                    Slice::Relative { .. },
+                ) if inner.subarrays.iter().all(|r| r.len() <= end) => Some(s1.clone()),
+                _ => v1.compose_ragged(&s1),
+            };
+            let generation = match self.view {
+                Some(_) => self.generation,
+                None => self.inner.generation.load(Ordering::Acquire),
+            };
+            (v0, v1, v2, generation)
+        };
+        let v1 = match v1 {
+            Some(v1) => v1,
+            None => return self.materialize()?.slice(s0, s1, s2),
+        };
+        let v0 = v0.compose(&s0).map_err(|i| out_of_bounds(i, 0, v0.len()))?;
+        let v2 = v2.compose(&s2).map_err(|i| out_of_bounds(i, 2, v2.len()))?;
+        Ok(RaggedBufferView {
+            inner: self.inner.clone(),
+            view: Some((v0, v1, v2)),
+            generation,
+        })
+    }
+
+    // Selects the sequences `s0` of `self`.
+    fn select_sequences(&self, s0: Slice) -> PyResult<RaggedBufferView<T>> {
+        let (_, v1, v2) = self.slices()?;
+        self.slice(
+            s0,
+            Slice::Range {
+                start: 0,
+                end: v1.len(),
                 step: 1,
             },
-            Index::Slice(slice) => {
-                let indices = slice
-                    .as_ref(py)
-                    .indices(materialized.len()?.try_into().unwrap())?;
-                Slice::Range {
-                    start: indices.start as usize,
-                    end: indices.stop as usize,
-                    step: indices.step as usize,
-                }
-            }
-        };
-        let v2 = match i2 {
-            Index::PermutationNP(np) => {
-                Slice::Permutation(np.to_vec()?.into_iter().map(|x| x as usize).collect())
-            }
-            Index::Permutation(p) => Slice::Permutation(p),
-            Index::Int(i) => Slice::Range {
-                start: i,
-                end: i + 1,
+            Slice::Range {
+                start: 0,
+                end: v2.len(),
                 step: 1,
             },
-            Index::Slice(slice) => {
-                let indices = slice
-                    .as_ref(py)
-                    .indices(materialized.size2().try_into().unwrap())?;
-                Slice::Range {
-                    start: indices.start as usize,
-                    end: indices.stop as usize,
-                    step: indices.step as usize,
-                }
-            }
-        };
+        )
+    }
 
-        Ok(RaggedBufferView {
-            inner: materialized.inner,
-            view: Some((v0, v1, v2)),
+    fn get(&self) -> PyResult<RwLockReadGuard<RaggedBuffer<T>>> {
+        let guard = self.inner.buffer.read().map_err(|_| poisoned())?;
+        self.check_generation()?;
+        Ok(guard)
+    }
+
+    fn get_mut(&self) -> PyResult<RwLockWriteGuard<RaggedBuffer<T>>> {
+        let guard = self.inner.buffer.write().map_err(|_| poisoned())?;
+        self.check_generation()?;
+        self.check_writable()?;
+        Ok(guard)
+    }
+
+    fn check_writable(&self) -> PyResult<()> {
+        if self.is_frozen() {
+            return Err(exceptions::PyValueError::new_err(
+                "RaggedBuffer is frozen and cannot be modified. Call .clone() to get a modifiable copy.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Makes the storage of `self` read-only.
+    /// Any subsequent attempt to modify `self`, the buffer it is a view of, or any other view of the same storage raises an error.
+    /// Copies created with `materialize` or `deepclone` are not frozen.
+    pub fn freeze(&self) -> PyResult<()> {
+        // Acquiring the write lock waits for in-progress modifications to complete.
+        let _guard = self.inner.buffer.write().map_err(|_| poisoned())?;
+        self.inner.frozen.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.inner.frozen.load(Ordering::Acquire)
+    }
+
+    // Checks that the shape of the storage has not changed since `self` was created.
+    // Buffers that are not views always cover the entire storage and remain valid.
+    fn check_generation(&self) -> PyResult<()> {
+        if self.view.is_some() && self.inner.generation.load(Ordering::Acquire) != self.generation {
+            return Err(exceptions::PyRuntimeError::new_err(
+                "View of RaggedBuffer is no longer valid since the buffer was modified by push, extend, or clear. Create a new view or call .materialize() before modifying the buffer.",
+            ));
+        }
+        Ok(())
+    }
+
+    // Locks `self` for an operation that changes its shape.
+    // Views are first copied into new storage, and existing views of the storage of buffers are invalidated.
+    fn get_resize(&mut self) -> PyResult<RwLockWriteGuard<'_, RaggedBuffer<T>>> {
+        self.check_writable()?;
+        self.make_contiguous()?;
+        let guard = self.get_mut()?;
+        self.inner.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(guard)
+    }
+
+    // Whether `self` and `other` are backed by the same storage.
+    fn shares_storage<U>(&self, other: &RaggedBufferView<U>) -> bool {
+        Arc::as_ptr(&self.inner) as *const () == Arc::as_ptr(&other.inner) as *const ()
+    }
+
+    // Storages are always locked in order of their addresses, so that threads that lock the same storages
+    // cannot deadlock by waiting for each other.
+    fn storage_address(&self) -> usize {
+        Arc::as_ptr(&self.inner) as *const () as usize
+    }
+
+    // Locks the storages of `self` and `other` for reading.
+    // If they share storage, it is only locked once and `None` is returned for `other`,
+    // since a second read lock on the same storage may deadlock with a pending writer.
+    fn get_with<'a, U: numpy::Element + Copy + Display + std::fmt::Debug + Sync>(
+        &'a self,
+        other: &'a RaggedBufferView<U>,
+    ) -> PyResult<(ReadGuard<'a, T>, Option<ReadGuard<'a, U>>)> {
+        if self.shares_storage(other) {
+            let guard = self.get()?;
+            other.check_generation()?;
+            Ok((guard, None))
+        } else if self.storage_address() < other.storage_address() {
+            let guard = self.get()?;
+            Ok((guard, Some(other.get()?)))
+        } else {
+            let other = other.get()?;
+            Ok((self.get()?, Some(other)))
+        }
+    }
+
+    // Locks the storage of `self` for writing and the storage of `other` for reading.
+    // `other` must not share storage with `self`, see `snapshot`.
+    fn get_mut_with<'a, U: numpy::Element + Copy + Display + std::fmt::Debug + Sync>(
+        &'a self,
+        other: &'a RaggedBufferView<U>,
+    ) -> PyResult<(WriteGuard<'a, T>, ReadGuard<'a, U>)> {
+        debug_assert!(!self.shares_storage(other));
+        if self.storage_address() < other.storage_address() {
+            let guard = self.get_mut()?;
+            Ok((guard, other.get()?))
+        } else {
+            let other = other.get()?;
+            Ok((self.get_mut()?, other))
+        }
+    }
+
+    // Returns the view of `self`, or slices covering the entire buffer if `self` is not a view.
+    fn slices(&self) -> PyResult<(Slice, Slice, Slice)> {
+        Ok(match &self.view {
+            Some(view) => {
+                self.check_generation()?;
+                view.clone()
+            }
+            None => full_slices(&*self.get()?),
         })
     }
 
-    fn get(&self) -> RwLockReadGuard<RaggedBuffer<T>> {
-        self.inner.read().unwrap()
+    // Like `slices`, but takes the already locked storage of `self`.
+    fn slices_of(&self, inner: &RaggedBuffer<T>) -> (Slice, Slice, Slice) {
+        match &self.view {
+            Some(view) => view.clone(),
+            None => full_slices(inner),
+        }
     }
 
-    fn get_mut(&self) -> RwLockWriteGuard<RaggedBuffer<T>> {
-        self.inner.write().unwrap()
+    fn make_contiguous(&mut self) -> PyResult<()> {
+        if self.view.is_some() {
+            *self = self.materialize()?;
+        }
+        Ok(())
     }
 
-    fn make_contiguous(&mut self) {
-        let materialized = self.materialize();
-        self.inner = materialized.inner;
-        self.view = None;
+    /// Returns `self` if it is not a view, and a materialized copy of `self` otherwise.
+    /// Unlike `materialize`, the result may share storage with `self`.
+    pub fn contiguous(&self) -> PyResult<RaggedBufferView<T>> {
+        match self.view {
+            None => Ok(self.clone()),
+            Some(_) => self.materialize(),
+        }
     }
     fn require_contiguous(&self, method_name: &str) -> PyResult<()> {
         match self.view {
@@ -150,36 +613,51 @@ impl<T: numpy::Element + Copy + Display + std::fmt::Debug> RaggedBufferView<T> {
         }
     }
 
-    pub fn from_array(data: PyReadonlyArray3<T>) -> Self {
-        RaggedBufferView {
-            inner: Arc::new(RwLock::new(RaggedBuffer::from_array(data.as_array()))),
-            view: None,
-        }
+    pub fn from_array(data: ArrayView3<T>) -> Self {
+        RaggedBuffer::from_array(data).view()
+    }
+
+    pub fn from_flattened(data: ArrayView2<T>, lengths: ArrayView1<i64>) -> PyResult<Self> {
+        Ok(RaggedBuffer::from_flattened(data, lengths)?.view())
+    }
+
+    pub fn from_segment_ids(
+        data: ArrayView2<T>,
+        segment_ids: ArrayView1<i64>,
+        num_segments: usize,
+    ) -> PyResult<(Self, Vec<i64>)> {
+        let (buffer, permutation) =
+            RaggedBuffer::from_segment_ids_with_permutation(data, segment_ids, num_segments)?;
+        Ok((
+            buffer.view(),
+            permutation.into_iter().map(|i| i as i64).collect(),
+        ))
+    }
+
+    pub fn from_padded(data: ArrayView3<T>, lengths: ArrayView1<i64>) -> PyResult<Self> {
+        Ok(RaggedBuffer::from_padded(data, lengths)?.view())
     }
 
-    pub fn from_flattened(
-        data: PyReadonlyArray2<T>,
-        lengths: PyReadonlyArray1<i64>,
+    pub fn from_padded_mask(data: ArrayView3<T>, mask: ArrayView2<bool>) -> PyResult<Self> {
+        Ok(RaggedBuffer::from_padded_mask(data, mask)?.view())
+    }
+
+    pub fn from_padpacked(
+        packed: ArrayView3<T>,
+        inverse_index: ArrayView1<i64>,
+        lengths: ArrayView1<i64>,
     ) -> PyResult<Self> {
-        Ok(RaggedBufferView {
-            inner: Arc::new(RwLock::new(RaggedBuffer::from_flattened(
-                data.as_array(),
-                lengths.as_array(),
-            )?)),
-            view: None,
-        })
+        Ok(RaggedBuffer::from_padpacked(packed, inverse_index, lengths)?.view())
     }
 
     pub fn extend(&mut self, other: &RaggedBufferView<T>) -> PyResult<()> {
-        self.make_contiguous();
-        let other = other.materialize();
-        let other = other.get();
-        self.get_mut().extend(&*other).map_err(Into::into)
+        let other = self.snapshot(other)?.contiguous()?;
+        let other = other.get()?;
+        self.get_resize()?.extend(&*other).map_err(Into::into)
     }
 
     pub fn clear(&mut self) -> PyResult<()> {
-        self.make_contiguous();
-        self.get_mut().clear();
+        self.get_resize()?.clear();
         Ok(())
     }
 
@@ -189,19 +667,20 @@ impl<T: numpy::Element + Copy + Display + std::fmt::Debug> RaggedBufferView<T> {
     ) -> PyResult<&'a numpy::PyArray<T, numpy::ndarray::Dim<[usize; 2]>>> {
         match self.view {
             None => {
-                let inner = self.get();
+                let inner = self.get()?;
                 inner
                     .data
                     .to_pyarray(py)
                     .reshape((inner.items(), inner.features))
                     .map_err(Into::into)
             }
-            _ => self.materialize().as_array(py),
+            _ => self.materialize()?.as_array(py),
         }
     }
 
-    pub fn materialize(&self) -> RaggedBufferView<T> {
-        match self.view.clone() {
+    /// Copies the elements of `self` into new storage that is not shared with any other buffer or view.
+    pub fn materialize(&self) -> PyResult<RaggedBufferView<T>> {
+        Ok(match self.view.clone() {
             Some((
                 Slice::Range {
                     start: start0,
@@ -219,184 +698,341 @@ impl<T: numpy::Element + Copy + Display + std::fmt::Debug> RaggedBufferView<T> {
                     step: step2,
                 },
             )) => {
-                let mut data = Vec::new();
-                let mut subarrays = Vec::new();
-                let mut item = 0;
-                let inner = self.get();
-                for i0 in (start0..end0).step_by(step0) {
-                    let mut items = 0;
-                    for i1 in inner.subarrays[i0]
+                let guard = self.get()?;
+                let inner = &*guard;
+                let sequences = (start0..end0).step_by(step0).collect::<Vec<_>>();
+                let items_of = |i0: usize| {
+                    inner.subarrays[i0]
                         .clone()
                         .skip(start1)
                         .take(end1 - start1)
                         .step_by(step1)
-                    {
-                        for i2 in (start2..end2).step_by(step2) {
-                            data.push(inner.data[i1 * inner.features + i2]);
-                        }
-                        items += 1;
-                    }
+                };
+                let features = (end2 - start2).div_ceil(step2);
+                let mut subarrays = Vec::with_capacity(sequences.len());
+                let mut item = 0;
+                for &i0 in &sequences {
+                    let items = items_of(i0).len();
                     subarrays.push(item..item + items);
                     item += items;
                 }
-                let features = (end2 - start2 + step2 - 1) / step2;
+                let data = flat_map_collect(sequences.len(), item * features, |i| {
+                    items_of(sequences[i]).flat_map(move |i1| {
+                        (start2..end2)
+                            .step_by(step2)
+                            .map(move |i2| inner.data[i1 * inner.features + i2])
+                    })
+                });
                 let materialized = RaggedBuffer {
                     data,
                     subarrays,
                     features,
                 };
-                RaggedBufferView {
-                    inner: Arc::new(RwLock::new(materialized)),
-                    view: None,
-                }
+                materialized.view()
             }
             Some((v0, v1, v2)) => {
-                let mut data = Vec::new();
-                let mut items = 0;
-                let mut subarrays = Vec::new();
-                let inner = self.get();
-                for i0 in v0.into_iter() {
-                    let item_start = items;
-                    let subarray = inner.subarrays[i0].clone();
-                    for i1 in v1.clone().into_iter() {
-                        if i1 >= subarray.len() {
-                            break;
-                        }
-                        let offset = (subarray.start + i1) * inner.features;
-                        for i2 in v2.clone().into_iter() {
-                            data.push(inner.data[offset + i2]);
-                        }
-                        items += 1;
-                    }
-                    subarrays.push(item_start..items);
-                }
+                let guard = self.get()?;
+                let inner = &*guard;
+                let sequences = v0.into_iter().collect::<Vec<_>>();
+                let items_of = |i0: usize| {
+                    let Range { start, end } = inner.subarrays[i0];
+                    v1.ragged_items(end - start).map(move |i1| start + i1)
+                };
                 let features = v2.len();
+                let mut subarrays = Vec::with_capacity(sequences.len());
+                let mut item = 0;
+                for &i0 in &sequences {
+                    let items = items_of(i0).count();
+                    subarrays.push(item..item + items);
+                    item += items;
+                }
+                let data = flat_map_collect(sequences.len(), item * features, |i| {
+                    let v2 = &v2;
+                    items_of(sequences[i]).flat_map(move |i1| {
+                        v2.clone()
+                            .into_iter()
+                            .map(move |i2| inner.data[i1 * inner.features + i2])
+                    })
+                });
                 let materialized = RaggedBuffer {
                     data,
                     subarrays,
                     features,
                 };
-                RaggedBufferView {
-                    inner: Arc::new(RwLock::new(materialized)),
-                    view: None,
-                }
+                materialized.view()
             }
-            None => self.clone(),
-        }
+            None => self.get()?.clone().view(),
+        })
     }
 
-    pub fn push(&mut self, x: &PyReadonlyArray2<T>) -> PyResult<()> {
-        self.make_contiguous();
-        self.get_mut().push(&x.as_array()).map_err(Into::into)
+    pub fn push(&mut self, x: &ArrayView2<T>) -> PyResult<()> {
+        self.get_resize()?.push(x).map_err(Into::into)
     }
 
     pub fn push_empty(&mut self) -> PyResult<()> {
-        self.make_contiguous();
-        self.get_mut().push_empty();
+        self.get_resize()?.push_empty();
         Ok(())
     }
 
-    pub fn swizzle(&self, indices: PyReadonlyArray1<i64>) -> PyResult<RaggedBufferView<T>> {
+    pub fn swizzle(&self, indices: ArrayView1<i64>) -> PyResult<RaggedBufferView<T>> {
         match self.view {
-            Some((_, _, _)) => todo!(),
-            None => Ok(self.get().swizzle(indices.as_array())?.view()),
+            Some((_, _, _)) => Ok(self
+                .select_sequences(Slice::Permutation(
+                    indices.iter().map(|&i| i as usize).collect(),
+                ))?
+                .materialize()?),
+            None => Ok(self.get()?.swizzle(indices)?.view()),
         }
     }
 
     pub fn swizzle_usize(&self, indices: &[usize]) -> PyResult<RaggedBufferView<T>> {
         match self.view {
-            Some((_, _, _)) => todo!(),
-            None => Ok(self.get().swizzle_usize(indices)?.view()),
+            Some((_, _, _)) => Ok(self
+                .select_sequences(Slice::Permutation(indices.to_vec()))?
+                .materialize()?),
+            None => Ok(self.get()?.swizzle_usize(indices)?.view()),
         }
     }
 
-    pub fn get_sequence(&self, i: usize) -> PyResult<RaggedBufferView<T>> {
-        self.require_contiguous("get_sequence")?;
-        Ok(self.get().get(i).view())
+    pub fn argsort_lengths(&self, descending: bool) -> PyResult<Vec<i64>> {
+        Ok(argsort_lengths(&self.sequence_lengths()?, descending)
+            .into_iter()
+            .map(|i| i as i64)
+            .collect())
     }
 
-    pub fn size0(&self) -> usize {
-        match &self.view {
-            Some((s0, _, _)) => s0.len(),
-            None => self.get().size0(),
-        }
+    /// Orders the sequences by length and returns the permutation that restores the original order.
+    pub fn sorted_by_length(&self, descending: bool) -> PyResult<(RaggedBufferView<T>, Vec<i64>)> {
+        let permutation = argsort_lengths(&self.sequence_lengths()?, descending);
+        let sorted = self.swizzle_usize(&permutation)?;
+        let inverse = inverse_permutation(&permutation)?;
+        Ok((sorted, inverse.into_iter().map(|i| i as i64).collect()))
     }
 
-    pub fn size2(&self) -> usize {
-        match &self.view {
-            Some((_, _, s2)) => s2.len(),
-            None => self.get().size2(),
+    pub fn get_sequence(&self, i: usize) -> PyResult<RaggedBufferView<T>> {
+        match self.view {
+            Some((_, _, _)) => Ok(self
+                .select_sequences(Slice::Permutation(vec![i]))?
+                .materialize()?),
+            None => Ok(self.get()?.get(i).view()),
         }
     }
 
+    pub fn size0(&self) -> PyResult<usize> {
+        Ok(match &self.view {
+            Some((s0, _, _)) => {
+                self.check_generation()?;
+                s0.len()
+            }
+            None => self.get()?.size0(),
+        })
+    }
+
+    pub fn size2(&self) -> PyResult<usize> {
+        Ok(match &self.view {
+            Some((_, _, s2)) => {
+                self.check_generation()?;
+                s2.len()
+            }
+            None => self.get()?.size2(),
+        })
+    }
+
+    // Number of items in each sequence of `self`.
+    fn sequence_lengths(&self) -> PyResult<Vec<usize>> {
+        let inner = self.get()?;
+        Ok(match &self.view {
+            None => inner.subarrays.iter().map(|r| r.len()).collect(),
+            Some((v0, v1, _)) => v0
+                .iter()
+                .map(|i0| v1.ragged_len(inner.subarrays[i0].len()))
+                .collect(),
+        })
+    }
+
     pub fn lengths<'a>(
         &self,
         py: Python<'a>,
     ) -> PyResult<&'a numpy::PyArray<i64, numpy::ndarray::Dim<[usize; 1]>>> {
-        match self.view {
-            None => Ok(self.get().lengths().to_pyarray(py)),
-            Some((
-                Slice::Range {
-                    start: start0,
-                    end: end0,
-                    step: step0,
-                },
-                Slice::Range {
-                    start: start1,
-                    end: end1,
-                    step: step1,
-                },
-                _,
-            )) => {
-                let mut lengths = Vec::with_capacity((end0 - start0) / step0);
-                let inner = self.get();
-                for i0 in (start0..end0).step_by(step0) {
-                    let end1 = std::cmp::min(end1, inner.subarrays[i0].len());
-                    if end1 > start1 {
-                        let stepsf = (end1 - start1) / step1;
-                        lengths.push(
-                            stepsf as i64 + if stepsf * step1 < end1 - start1 { 1 } else { 0 },
-                        );
-                    } else {
-                        lengths.push(0);
-                    }
-                }
-                Ok(lengths.to_pyarray(py))
-            }
-            _ => {
-                self.require_contiguous("lengths")?;
-                Ok(self.get().lengths().to_pyarray(py))
-            }
-        }
+        Ok(self
+            .sequence_lengths()?
+            .into_iter()
+            .map(|len| len as i64)
+            .collect::<Vec<_>>()
+            .to_pyarray(py))
+    }
+
+    pub fn cu_seqlens(&self) -> PyResult<Vec<i64>> {
+        let mut offset = 0;
+        Ok(std::iter::once(0)
+            .chain(self.sequence_lengths()?.into_iter().map(|len| {
+                offset += len as i64;
+                offset
+            }))
+            .collect())
     }
 
-    pub fn size1(&mut self, i: usize) -> PyResult<usize> {
-        self.make_contiguous();
-        self.get().size1(i).map_err(Into::into)
+    pub fn max_seqlen(&self) -> PyResult<usize> {
+        Ok(self.sequence_lengths()?.into_iter().max().unwrap_or(0))
+    }
+
+    pub fn segment_ids(&self) -> PyResult<Vec<i64>> {
+        Ok(self
+            .sequence_lengths()?
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, len)| std::iter::repeat_n(i as i64, len))
+            .collect())
+    }
+
+    /// Splits the sequences of `self` into shuffled minibatches of sequence indices, see `sampler::minibatches`.
+    pub fn minibatches(&self, seed: u64, options: &MinibatchOptions) -> PyResult<Vec<Vec<i64>>> {
+        let batches = minibatches(&self.sequence_lengths()?, options, seed)?;
+        Ok(batches
+            .into_iter()
+            .map(|batch| batch.into_iter().map(|i| i as i64).collect())
+            .collect())
+    }
+
+    pub fn size1(&self, i: usize) -> PyResult<usize> {
+        let inner = self.get()?;
+        match &self.view {
+            None => inner.size1(i).map_err(Into::into),
+            Some((v0, _, _)) if i >= v0.len() => {
+                Err(Error::generic(format!("Index {} out of range", i)).into())
+            }
+            Some((v0, v1, _)) => Ok(v1.ragged_len(inner.subarrays[v0.at(i)].len())),
+        }
     }
 
     pub fn __str__(&self) -> PyResult<String> {
-        self.materialize().get().__str__().map_err(Into::into)
+        self.contiguous()?.get()?.__str__().map_err(Into::into)
     }
 
+    /// Applies `Op` elementwise with the same broadcasting rules as `RaggedBuffer::binop`.
+    /// Either operand may be a view, in which case its elements are read directly from the underlying storage.
     pub fn binop<Op: BinOp<T>>(&self, rhs: &RaggedBufferView<T>) -> PyResult<RaggedBufferView<T>> {
-        self.require_contiguous("binop")?;
-        Ok(self.get().binop::<Op>(&*rhs.get())?.view())
+        if self.view.is_none() && rhs.view.is_none() {
+            let (lhs, rhs) = self.get_with(rhs)?;
+            return Ok(lhs.binop::<Op>(rhs.as_deref().unwrap_or(&lhs))?.view());
+        }
+        self.zip_map(rhs, Op::op)
+    }
+
+    // Computes `f(lhs, rhs)` for each pair of elements of `self` and `rhs` broadcast to a common shape.
+    fn zip_map<R: Send, F: Fn(T, T) -> R + Send + Sync>(
+        &self,
+        rhs: &RaggedBufferView<T>,
+        f: F,
+    ) -> PyResult<RaggedBufferView<R>> {
+        let (lhs_guard, rhs_guard) = self.get_with(rhs)?;
+        let rhs_inner = rhs_guard.as_deref().unwrap_or(&lhs_guard);
+        let lhs = ResolvedView::new(&lhs_guard, self.slices_of(&lhs_guard));
+        let rhs = ResolvedView::new(rhs_inner, rhs.slices_of(rhs_inner));
+        let shape = broadcast_shape(
+            &lhs.lengths,
+            lhs.features.len(),
+            &rhs.lengths,
+            rhs.features.len(),
+        )?;
+        let features = shape.features;
+        let mut subarrays = Vec::with_capacity(shape.lengths.len());
+        let mut item = 0;
+        for &len in &shape.lengths {
+            subarrays.push(item..item + len);
+            item += len;
+        }
+        let (lhs, rhs, shape, f) = (&lhs, &rhs, &shape, &f);
+        let data = flat_map_collect(shape.lengths.len(), item * features, |iseq| {
+            let lhs_items = lhs.items_of(iseq % lhs.sequences.len()).collect::<Vec<_>>();
+            let rhs_items = rhs.items_of(iseq % rhs.sequences.len()).collect::<Vec<_>>();
+            let lhs_features = lhs.features.len();
+            let rhs_features = rhs.features.len();
+            (0..shape.lengths[iseq]).flat_map(move |i1| {
+                let l1 = lhs_items[if lhs_items.len() == 1 { 0 } else { i1 }];
+                let r1 = rhs_items[if rhs_items.len() == 1 { 0 } else { i1 }];
+                (0..features).map(move |i2| {
+                    f(
+                        lhs.get(l1, if lhs_features == 1 { 0 } else { i2 }),
+                        rhs.get(r1, if rhs_features == 1 { 0 } else { i2 }),
+                    )
+                })
+            })
+        });
+        Ok(RaggedBuffer {
+            data,
+            subarrays,
+            features,
+        }
+        .view())
+    }
+
+    pub fn binop_dense<Op: BinOp<T>>(&self, rhs: ArrayViewD<T>) -> PyResult<RaggedBufferView<T>> {
+        self.binop::<Op>(&RaggedBuffer::from_dense(rhs)?.view())
+    }
+
+    pub fn binop_dense_mut<Op: BinOp<T>>(&self, rhs: ArrayViewD<T>) -> PyResult<()> {
+        self.binop_mut::<Op>(&RaggedBuffer::from_dense(rhs)?.view())
     }
 
     pub fn op_scalar<Op: BinOp<T>>(&self, scalar: T) -> PyResult<RaggedBufferView<T>> {
-        self.require_contiguous("op_scalar")?;
-        Ok(self.get().op_scalar::<Op>(scalar).view())
+        if self.view.is_none() {
+            return Ok(self.get()?.op_scalar::<Op>(scalar).view());
+        }
+        // Materializing a view produces a fresh buffer, so the result can be computed in place.
+        let result = self.materialize()?;
+        result.get_mut()?.op_scalar_assign::<Op>(scalar);
+        Ok(result)
+    }
+
+    pub fn indices(&self, dim: usize) -> PyResult<RaggedBufferView<i64>> {
+        Ok(self.contiguous()?.get()?.indices(dim)?.view())
     }
 
-    pub fn indices(&mut self, dim: usize) -> PyResult<RaggedBufferView<i64>> {
-        self.make_contiguous();
-        Ok(self.get().indices(dim)?.view())
+    pub fn flat_indices(&self) -> PyResult<RaggedBufferView<i64>> {
+        Ok(self.contiguous()?.get()?.flat_indices()?.view())
     }
 
-    pub fn flat_indices(&mut self) -> PyResult<RaggedBufferView<i64>> {
-        self.make_contiguous();
-        Ok(self.get().flat_indices()?.view())
+    /// Splits `self` into multiple buffers along one dimension, the inverse of `cat`.
+    pub fn split(&self, sizes: SplitSizes) -> PyResult<Vec<RaggedBufferView<T>>> {
+        // Lengths are copied out first so that no two locks of the same storage are held at once.
+        let sizes = match sizes {
+            SplitSizes::Lengths(lengths) => SplitSizes::Items(lengths.part_lengths()?),
+            sizes => sizes,
+        };
+        let buffer = self.contiguous()?;
+        let buffer = buffer.get()?;
+        let parts = match sizes {
+            SplitSizes::Sequences(sizes) => buffer.split_sequences(&sizes)?,
+            SplitSizes::Items(lengths) => buffer.split_items(&lengths)?,
+            SplitSizes::Lengths(_) => unreachable!(),
+            SplitSizes::SplitPoints(points) => {
+                if points.len() != buffer.size0() {
+                    return Err(Error::generic(format!(
+                        "Split points have {} sequences, but buffer has {} sequences",
+                        points.len(),
+                        buffer.size0()
+                    ))
+                    .into());
+                }
+                let rest = points
+                    .iter()
+                    .zip(&buffer.subarrays)
+                    .map(|(&point, r)| {
+                        r.len().checked_sub(point).ok_or_else(|| {
+                            Error::generic(format!(
+                                "Split point {} exceeds sequence length {}",
+                                point,
+                                r.len()
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                buffer.split_items(&[points, rest])?
+            }
+            SplitSizes::Features(sizes) => buffer.split_features(&sizes)?,
+        };
+        Ok(parts.into_iter().map(RaggedBuffer::view).collect())
     }
 
     pub fn cat(buffers: &[&RaggedBufferView<T>], dim: usize) -> PyResult<RaggedBufferView<T>> {
@@ -405,120 +1041,199 @@ impl<T: numpy::Element + Copy + Display + std::fmt::Debug> RaggedBufferView<T> {
                 "cat requires at least one ragged buffer",
             ));
         }
-        let mut rbs = Vec::new();
-        for b in buffers {
+        // Lock each distinct storage only once, since buffers may be passed more than once,
+        // and in order of their addresses.
+        let mut distinct = Vec::with_capacity(buffers.len());
+        for &b in buffers {
             b.require_contiguous("cat")?;
-            rbs.push(b.get());
+            distinct.push(b);
         }
-        let rb = RaggedBuffer::cat(&rbs.iter().map(|r| &**r).collect::<Vec<_>>(), dim)?;
-        Ok(RaggedBufferView {
-            inner: Arc::new(RwLock::new(rb)),
-            view: None,
-        })
+        distinct.sort_by_key(|b| b.storage_address());
+        distinct.dedup_by_key(|b| b.storage_address());
+        let guards = distinct
+            .into_iter()
+            .map(|b| Ok((b, b.get()?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let rbs = buffers
+            .iter()
+            .map(|b| {
+                let (_, guard) = guards.iter().find(|(g, _)| g.shares_storage(b)).unwrap();
+                &**guard
+            })
+            .collect::<Vec<_>>();
+        let rb = RaggedBuffer::cat(&rbs, dim)?;
+        Ok(rb.view())
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn padpack(&mut self) -> PyResult<Option<(Vec<i64>, Vec<f32>, Vec<i64>, (usize, usize))>> {
-        self.make_contiguous();
-        Ok(self.get().padpack())
+    pub fn padpack(
+        &self,
+        capacity: Option<usize>,
+        strategy: &str,
+        always: bool,
+    ) -> PyResult<Option<Padpack>> {
+        let options = PadpackOptions {
+            capacity,
+            strategy: strategy.parse::<PackingStrategy>()?,
+            always,
+        };
+        Ok(self.contiguous()?.get()?.padpack_with(&options)?)
+    }
+
+    pub fn to_padded(
+        &self,
+        max_len: Option<usize>,
+        pad_value: T,
+        side: &str,
+        truncate: &str,
+    ) -> PyResult<(Array3<T>, Array2<bool>)> {
+        let side = side.parse::<PadSide>()?;
+        let truncate = truncate.parse::<Truncate>()?;
+        Ok(self
+            .contiguous()?
+            .get()?
+            .to_padded(max_len, pad_value, side, truncate))
     }
 
-    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> PyResult<usize> {
-        self.require_contiguous("len")?;
-        Ok(self.get().len())
+        Ok(self.items()? * self.size2()?)
     }
 
-    pub fn is_empty(&mut self) -> PyResult<bool> {
-        self.make_contiguous();
-        Ok(self.get().is_empty())
+    pub fn is_empty(&self) -> PyResult<bool> {
+        Ok(self.len()? == 0)
     }
 
-    pub fn items(&mut self) -> PyResult<usize> {
-        self.make_contiguous();
-        Ok(self.get().items())
+    pub fn items(&self) -> PyResult<usize> {
+        Ok(match &self.view {
+            None => self.get()?.items(),
+            Some(_) => self.sequence_lengths()?.into_iter().sum(),
+        })
     }
 
+    /// Computes `self = self <op> rhs` in place, writing through to the storage of `self` if it is a view.
+    /// `rhs` may share storage with `self`, in which case the elements of `rhs` are copied before `self` is modified.
     pub fn binop_mut<Op: BinOp<T>>(&self, rhs: &RaggedBufferView<T>) -> PyResult<()> {
-        let (lhs_i0, lhs_i1, lhs_i2) = self.view.clone().unwrap();
-        let (rhs_i0, rhs_i1, rhs_i2) = rhs.view.clone().unwrap();
+        let rhs = self.snapshot(rhs)?;
+        if self.view.is_none() {
+            let rhs = rhs.contiguous()?;
+            let (mut lhs, rhs) = self.get_mut_with(&rhs)?;
+            return lhs.binop_assign::<Op>(&rhs).map_err(Into::into);
+        }
+        let (mut inner, guard) = self.get_mut_with(&rhs)?;
+        let rhs = ResolvedView::new(&guard, rhs.slices_of(&guard));
+        self.check_broadcast_in_place(&inner, &rhs.lengths, rhs.features.len())?;
+        self.update(&mut inner, |x, iseq, item, feature| {
+            Op::op(x, rhs.broadcast_get(iseq, item, feature))
+        });
+        Ok(())
+    }
 
-        let (lhs_iter_0, rhs_iter_0) = if self.size0() == rhs.size0() {
-            (lhs_i0.into_iter(), rhs_i0.into_iter())
-        } else {
-            return Err(exceptions::PyValueError::new_err(format!(
-                "size mismatch in first dimension: {} != {}",
-                self.size0(),
-                rhs.size0(),
-            )));
-        };
-        assert!(matches!(lhs_i1, Slice::Range { .. }));
-        assert!(matches!(rhs_i1, Slice::Range { .. }));
-        if self.size2() != rhs.size2() {
-            return Err(exceptions::PyValueError::new_err(format!(
-                "size mismatch in third dimension: {} != {}",
-                self.size2(),
-                rhs.size2(),
-            )));
-        };
+    pub fn op_scalar_mut<Op: BinOp<T>>(&self, scalar: T) -> PyResult<()> {
+        match self.view {
+            None => {
+                self.get_mut()?.op_scalar_assign::<Op>(scalar);
+                Ok(())
+            }
+            Some(_) => {
+                self.update(&mut *self.get_mut()?, |x, _, _, _| Op::op(x, scalar));
+                Ok(())
+            }
+        }
+    }
 
-        let stride2l = self.get().size2();
-        let stride2r = rhs.get().size2();
-
-        let mut lhs = self.get_mut();
-        let rhs = rhs.get();
-        for (l0, r0) in lhs_iter_0.zip(rhs_iter_0) {
-            let (lhs_iter_1, rhs_iter_1): (
-                Box<dyn Iterator<Item = usize>>,
-                Box<dyn Iterator<Item = usize>>,
-            ) = if lhs.subarrays[l0].len() != rhs.subarrays[r0].len() {
-                if lhs.subarrays[l0].len() == 1 {
-                    (
-                        Box::new(
-                            vec![lhs.subarrays[l0].start; rhs.subarrays[r0].len()].into_iter(),
-                        ),
-                        Box::new(rhs.subarrays[r0].clone()),
-                    )
-                } else if rhs.subarrays[r0].len() == 1 {
-                    (
-                        Box::new(lhs.subarrays[l0].clone()),
-                        Box::new(
-                            vec![rhs.subarrays[r0].start; lhs.subarrays[l0].len()].into_iter(),
-                        ),
-                    )
-                } else {
-                    return Err(exceptions::PyValueError::new_err(format!(
-                        "size mismatch between {}th and {}th sequence: {} != {}",
-                        l0,
-                        r0,
-                        lhs.subarrays[l0].len(),
-                        rhs.subarrays[r0].len(),
-                    )));
-                }
+    /// Sets the elements of `self` for which `mask` is true to the corresponding element of `value`.
+    /// Both `mask` and `value` are broadcast to the shape of `self`.
+    pub fn assign_masked(
+        &self,
+        mask: &RaggedBufferView<bool>,
+        value: &RaggedBufferView<T>,
+    ) -> PyResult<()> {
+        // The mask is copied into new storage that no other thread can lock,
+        // so that only the storages of `self` and `value` have to be locked in a consistent order.
+        let mask = mask.materialize()?;
+        let mask_guard = mask.get()?;
+        let mask = ResolvedView::new(&mask_guard, mask.slices_of(&mask_guard));
+        let value = self.snapshot(value)?;
+        let (mut inner, value_guard) = self.get_mut_with(&value)?;
+        let value = ResolvedView::new(&value_guard, value.slices_of(&value_guard));
+        self.check_broadcast_in_place(&inner, &mask.lengths, mask.features.len())?;
+        self.check_broadcast_in_place(&inner, &value.lengths, value.features.len())?;
+        self.update(&mut inner, |x, iseq, item, feature| {
+            if mask.broadcast_get(iseq, item, feature) {
+                value.broadcast_get(iseq, item, feature)
             } else {
-                (
-                    Box::new(lhs.subarrays[l0].clone()),
-                    Box::new(rhs.subarrays[r0].clone()),
-                )
-            };
-            for (l1, r1) in lhs_iter_1.zip(rhs_iter_1) {
-                for (l2, r2) in lhs_i2.clone().into_iter().zip(rhs_i2.clone().into_iter()) {
-                    lhs.data[l1 * stride2l + l2] =
-                        Op::op(lhs.data[l1 * stride2l + l2], rhs.data[r1 * stride2r + r2]);
-                }
+                x
             }
+        });
+        Ok(())
+    }
+
+    // Returns `other` if it does not share storage with `self`, and a copy of `other` otherwise,
+    // so that the result can be read while `self` is locked for writing.
+    fn snapshot<U: numpy::Element + Copy + Display + std::fmt::Debug + Sync>(
+        &self,
+        other: &RaggedBufferView<U>,
+    ) -> PyResult<RaggedBufferView<U>> {
+        if !self.shares_storage(other) {
+            Ok(other.clone())
+        } else if other.view.is_none() {
+            Ok(other.get()?.clone().view())
+        } else {
+            other.materialize()
         }
+    }
 
+    // Checks that an operand with the given sequence lengths and features can be broadcast to the shape of `self`.
+    fn check_broadcast_in_place(
+        &self,
+        inner: &RaggedBuffer<T>,
+        rhs_lengths: &[usize],
+        rhs_features: usize,
+    ) -> PyResult<()> {
+        let ResolvedView {
+            lengths, features, ..
+        } = ResolvedView::new(inner, self.slices_of(inner));
+        let features = features.len();
+        let shape = broadcast_shape(&lengths, features, rhs_lengths, rhs_features)?;
+        if shape.lengths != lengths || shape.features != features {
+            return Err(Error::generic(format!(
+                "Cannot broadcast in place: result has shape ({}, {:?}, {}), but lhs has shape ({}, {:?}, {})",
+                shape.lengths.len(),
+                shape.lengths,
+                shape.features,
+                lengths.len(),
+                lengths,
+                features,
+            ))
+            .into());
+        }
         Ok(())
     }
 
-    pub fn deepclone(&self) -> RaggedBufferView<T> {
-        let inner = self.get().clone();
-        RaggedBufferView {
-            inner: Arc::new(RwLock::new(inner)),
-            view: self.view.clone(),
+    // Replaces each element `x` of `self` with `f(x, iseq, item, feature)`, where `iseq`, `item`, and `feature`
+    // are the position of the element within `self`. `inner` is the storage of `self`, locked for writing.
+    fn update<F: Fn(T, usize, usize, usize) -> T>(&self, inner: &mut RaggedBuffer<T>, f: F) {
+        let (v0, v1, v2) = self.slices_of(inner);
+        let features = v2.iter().collect::<Vec<_>>();
+        let stride = inner.features;
+        for (iseq, i0) in v0.iter().enumerate() {
+            let Range { start, end } = inner.subarrays[i0];
+            for (item, i1) in v1.ragged_items(end - start).enumerate() {
+                for (feature, &i2) in features.iter().enumerate() {
+                    let index = (start + i1) * stride + i2;
+                    inner.data[index] = f(inner.data[index], iseq, item, feature);
+                }
+            }
         }
     }
+
+    pub fn deepclone(&self) -> PyResult<RaggedBufferView<T>> {
+        let inner = self.get()?.clone();
+        Ok(RaggedBufferView {
+            inner: Storage::new(inner),
+            view: self.view.clone(),
+            generation: 0,
+        })
+    }
 }
 
 pub fn translate_rotate(
@@ -526,119 +1241,417 @@ pub fn translate_rotate(
     translation: &RaggedBufferView<f32>,
     rotation: &RaggedBufferView<f32>,
 ) -> PyResult<()> {
-    if source.size0() != translation.size0() {
+    if source.size0()? != translation.size0()? {
         return Err(exceptions::PyValueError::new_err(format!(
             "size mismatch in first dimension: {} != {}",
-            source.size0(),
-            translation.size0(),
+            source.size0()?,
+            translation.size0()?,
         )));
     }
-    if source.size2() != 2 {
+    if source.size2()? != 2 {
         return Err(exceptions::PyValueError::new_err(format!(
             "expected 2D source, got {}D",
-            source.size2(),
+            source.size2()?,
         )));
     }
-    if translation.size2() != 2 {
+    if translation.size2()? != 2 {
         return Err(exceptions::PyValueError::new_err(format!(
             "expected 2D translation, got {}D",
-            translation.size2(),
+            translation.size2()?,
         )));
     }
-    if rotation.size2() != 2 {
+    if rotation.size2()? != 2 {
         return Err(exceptions::PyValueError::new_err(format!(
             "expected rotation to be a 2D direction, got {}D",
-            rotation.size2(),
+            rotation.size2()?,
         )));
     }
-    let (s0, _, s2) = source.view.clone().unwrap();
-    let (t0, _, t2) = translation.view.clone().unwrap();
-    let (r0, _, r2) = rotation.view.clone().unwrap();
-    let mut source = source.get_mut();
-    let translation = translation.get();
-    let rotation = rotation.get();
-
-    let ss0 = source.size0();
-    let ts0 = translation.size0();
-    let rs0 = rotation.size0();
-    match s0 {
-        Slice::Range { start, end, step } if start == 0 && end == ss0 && step == 1 => {}
-        _ => {
-            return Err(exceptions::PyValueError::new_err(
-                "view on first dimension of source not supported".to_string(),
-            ))
+    // Translation and rotation are copied into new storage that no other thread can lock,
+    // which also allows them to be views of the source.
+    let translation = translation.materialize()?;
+    let translation = translation.get()?;
+    let rotation = rotation.materialize()?;
+    let rotation = rotation.get()?;
+    let (translation, rotation) = (&*translation, &*rotation);
+    for i0 in 0..translation.size0() {
+        if translation.size1(i0)? != 1 || rotation.size1(i0)? != 1 {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "must have single item in translation and rotation for each sequence, but got {} and {} items for sequence {}",
+                translation.size1(i0)?, rotation.size1(i0)?, i0,
+            )));
         }
     }
-    match t0 {
-        Slice::Range { start, end, step } if start == 0 && end == ts0 && step == 1 => {}
-        _ => {
-            return Err(exceptions::PyValueError::new_err(
-                "view on first dimension of translation not supported".to_string(),
-            ))
+
+    let (s0, s1, s2) = source.slices()?;
+    let (sxi, syi) = (s2.at(0), s2.at(1));
+    let mut source = source.get_mut()?;
+    let sstride = source.features;
+    for (iseq, i0) in s0.iter().enumerate() {
+        let tx = translation.data[iseq * 2];
+        let ty = translation.data[iseq * 2 + 1];
+        let rx = rotation.data[iseq * 2];
+        let ry = rotation.data[iseq * 2 + 1];
+        let Range { start, end } = source.subarrays[i0];
+        for i1 in s1.ragged_items(end - start) {
+            let sstart = (start + i1) * sstride;
+            let sx = source.data[sstart + sxi] - tx;
+            let sy = source.data[sstart + syi] - ty;
+            source.data[sstart + sxi] = rx * sx + ry * sy;
+            source.data[sstart + syi] = -ry * sx + rx * sy;
         }
     }
-    match r0 {
-        Slice::Range { start, end, step } if start == 0 && end == rs0 && step == 1 => {}
-        _ => {
-            return Err(exceptions::PyValueError::new_err(
-                "view on first dimension of rotation not supported".to_string(),
+    Ok(())
+}
+
+// Converts a boolean mask into the positions it selects along dimension `dim` of a view with `len` positions.
+fn mask_to_slice(
+    mask: impl ExactSizeIterator<Item = bool>,
+    dim: usize,
+    len: usize,
+) -> PyResult<Slice> {
+    // Along the ragged dimension, the mask may be shorter than the longest sequence.
+    if dim != 1 && mask.len() != len {
+        return Err(exceptions::PyIndexError::new_err(format!(
+            "boolean index did not match dimension {} with size {}, got mask of length {}",
+            dim,
+            len,
+            mask.len()
+        )));
+    }
+    Ok(Slice::Permutation(
+        mask.enumerate()
+            .filter(|&(_, m)| m)
+            .map(|(i, _)| i)
+            .collect(),
+    ))
+}
+
+// Converts `index` into the positions it selects along dimension `dim` of a view with `len` positions.
+fn to_slice(py: Python, index: Index, dim: usize, len: usize) -> PyResult<Slice> {
+    Ok(match index {
+        Index::PermutationNP(np) => Slice::Permutation(
+            np.as_array()
+                .iter()
+                .map(|&i| to_index(i as isize, dim, len))
+                .collect::<PyResult<_>>()?,
+        ),
+        Index::MaskNP(mask) => mask_to_slice(mask.as_array().iter().copied(), dim, len)?,
+        // An empty list selects nothing, like an empty list of indices.
+        Index::Mask(mask) if mask.is_empty() => Slice::Permutation(vec![]),
+        Index::Mask(mask) => mask_to_slice(mask.into_iter(), dim, len)?,
+        Index::Ragged(_) => {
+            return Err(exceptions::PyIndexError::new_err(
+                "RaggedBufferI64 indices are only supported along dimension 1 and cannot be assigned to",
             ))
         }
+        Index::Permutation(p) => Slice::Permutation(
+            p.into_iter()
+                .map(|i| to_index(i as isize, dim, len))
+                .collect::<PyResult<_>>()?,
+        ),
+        // Negative integers along the ragged dimension count from the end of each sequence.
+        Index::Int(i) if dim == 1 && i < 0 => Slice::Relative {
+            start: Some(i),
+            stop: if i == -1 { None } else { Some(i + 1) },
+            step: 1,
+        },
+        Index::Int(i) => {
+            let i = to_index(i, dim, len)?;
+            Slice::Range {
+                start: i,
+                end: i + 1,
+                step: 1,
+            }
+        }
+        Index::Slice(slice) => {
+            let slice = slice.as_ref(py);
+            let start = slice.getattr("start")?.extract::<Option<isize>>()?;
+            let stop = slice.getattr("stop")?.extract::<Option<isize>>()?;
+            let step = slice
+                .getattr("step")?
+                .extract::<Option<isize>>()?
+                .unwrap_or(1);
+            if step == 0 {
+                return Err(exceptions::PyValueError::new_err(
+                    "slice step cannot be zero",
+                ));
+            }
+            let negative = |x: Option<isize>| x.is_some_and(|x| x < 0);
+            if dim == 1 && (step < 0 || negative(start) || negative(stop)) {
+                return Ok(Slice::Relative { start, stop, step });
+            }
+            let (start, len) = resolve_slice(start, stop, step, len);
+            if step > 0 {
+                Slice::Range {
+                    start: start as usize,
+                    end: start as usize + len * step as usize,
+                    step: step as usize,
+                }
+            } else {
+                Slice::Permutation(
+                    (0..len)
+                        .map(|k| (start + k as isize * step) as usize)
+                        .collect(),
+                )
+            }
+        }
+        Index::Ellipsis(_) => Slice::Range {
+            start: 0,
+            end: len,
+            step: 1,
+        },
+    })
+}
+
+// Converts a possibly negative index along dimension `dim` into a position.
+// Along the ragged dimension, indices are not bounds checked since they select nothing in sequences that are too short.
+fn to_index(i: isize, dim: usize, len: usize) -> PyResult<usize> {
+    if dim == 1 {
+        return usize::try_from(i).map_err(|_| {
+            exceptions::PyIndexError::new_err(
+                "negative indices along dimension 1 are only supported in integers and slices",
+            )
+        });
     }
-    let (sxi, syi) = match s2 {
-        Slice::Range { start, step, .. } => (start, start + step),
-        Slice::Permutation(indices) => (indices[0], indices[1]),
+    let position = if i < 0 { i + len as isize } else { i };
+    if position < 0 || position as usize >= len {
+        return Err(out_of_bounds(i, dim, len));
+    }
+    Ok(position as usize)
+}
+
+// Converts a possibly negative index of an item in sequence `iseq` with `len` items into a position.
+fn to_item_index(i: i64, iseq: usize, len: usize) -> PyResult<usize> {
+    let position = if i < 0 { i + len as i64 } else { i };
+    if position < 0 || position as usize >= len {
+        return Err(exceptions::PyIndexError::new_err(format!(
+            "index {} is out of bounds for sequence {} with {} items",
+            i, iseq, len
+        )));
+    }
+    Ok(position as usize)
+}
+
+// Resolves a Python slice for a dimension of length `len` like `slice.indices(len)`.
+// Returns the first selected index and the number of selected indices.
+fn resolve_slice(
+    start: Option<isize>,
+    stop: Option<isize>,
+    step: isize,
+    len: usize,
+) -> (isize, usize) {
+    let len = len as isize;
+    let clamp = |i: Option<isize>, default: isize| match i {
+        None => default,
+        Some(i) if i < 0 => (i + len).max(if step < 0 { -1 } else { 0 }),
+        Some(i) => i.min(if step < 0 { len - 1 } else { len }),
     };
-    let (txi, tyi) = match t2 {
-        Slice::Range { start, step, .. } => (start, start + step),
-        Slice::Permutation(indices) => (indices[0], indices[1]),
+    let (start, stop) = if step < 0 {
+        (clamp(start, len - 1), clamp(stop, -1))
+    } else {
+        (clamp(start, 0), clamp(stop, len))
     };
-    let (rxi, ryi) = match r2 {
-        Slice::Range { start, step, .. } => (start, start + step),
-        Slice::Permutation(indices) => (indices[0], indices[1]),
+    let count = if step < 0 && stop < start {
+        (start - stop - 1) / -step + 1
+    } else if step > 0 && start < stop {
+        (stop - start - 1) / step + 1
+    } else {
+        0
     };
-    let sstride = source.features;
-    for i0 in 0..source.size0() {
-        if translation.size1(i0)? != 1 || rotation.size1(i0)? != 1 {
-            return Err(exceptions::PyValueError::new_err(format!(
-                "must have single item in translation and rotation for each sequence, but got {} and {} items for sequence {}",
-                translation.size1(i0)?, rotation.size1(i0)?, i0,
-            )));
+    (start, count as usize)
+}
+
+fn poisoned() -> PyErr {
+    exceptions::PyRuntimeError::new_err(
+        "RaggedBuffer lock poisoned by a panic during a previous operation",
+    )
+}
+
+fn out_of_bounds(i: impl Display, dim: usize, len: usize) -> PyErr {
+    exceptions::PyIndexError::new_err(format!(
+        "index {} is out of bounds for dimension {} with size {}",
+        i, dim, len
+    ))
+}
+
+// Slices covering the entire buffer.
+fn full_slices<T>(inner: &RaggedBuffer<T>) -> (Slice, Slice, Slice) {
+    (
+        Slice::Range {
+            start: 0,
+            end: inner.subarrays.len(),
+            step: 1,
+        },
+        Slice::Range {
+            start: 0,
+            end: inner.subarrays.iter().map(|r| r.len()).max().unwrap_or(0),
+            step: 1,
+        },
+        Slice::Range {
+            start: 0,
+            end: inner.features,
+            step: 1,
+        },
+    )
+}
+
+impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Sync + PartialEq> RaggedBufferView<T> {
+    /// Whether `self` and `other` have the same shape and elements.
+    /// Either operand may be a view, and no broadcasting is performed.
+    pub fn equals(&self, other: &RaggedBufferView<T>) -> PyResult<bool> {
+        let (lhs_guard, rhs_guard) = self.get_with(other)?;
+        if rhs_guard.is_none() && self.view == other.view {
+            return Ok(true);
         }
-        // TODO: check no view on dim 1
-        for i1 in source.subarrays[i0].clone() {
-            let sstart = i1 * sstride;
-            source.data[sstart + sxi] -= translation.data[i0 * translation.features + txi];
-            source.data[sstart + syi] -= translation.data[i0 * translation.features + tyi];
-            let rx = rotation.data[i0 * rotation.features + rxi];
-            let ry = rotation.data[i0 * rotation.features + ryi];
-            let sx = source.data[sstart + sxi];
-            let sy = source.data[sstart + syi];
-            source.data[sstart + sxi] = rx * sx + ry * sy;
-            source.data[sstart + syi] = -ry * sx + rx * sy;
+        let rhs_inner = rhs_guard.as_deref().unwrap_or(&lhs_guard);
+        let lhs = ResolvedView::new(&lhs_guard, self.slices_of(&lhs_guard));
+        let rhs = ResolvedView::new(rhs_inner, other.slices_of(rhs_inner));
+        if lhs.lengths != rhs.lengths || lhs.features.len() != rhs.features.len() {
+            return Ok(false);
         }
+        Ok((0..lhs.sequences.len()).all(|iseq| {
+            lhs.items_of(iseq).zip(rhs.items_of(iseq)).all(|(l1, r1)| {
+                (0..lhs.features.len()).all(|i2| lhs.get(l1, i2) == rhs.get(r1, i2))
+            })
+        }))
+    }
+}
+
+impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Sync + PartialOrd> RaggedBufferView<T> {
+    pub fn sort_by_feature(
+        &self,
+        feature: usize,
+        descending: bool,
+    ) -> PyResult<RaggedBufferView<T>> {
+        Ok(self
+            .contiguous()?
+            .get()?
+            .sort_by_feature(feature, descending)?
+            .view())
+    }
+
+    pub fn argsort_within(
+        &self,
+        feature: usize,
+        descending: bool,
+    ) -> PyResult<RaggedBufferView<i64>> {
+        Ok(self
+            .contiguous()?
+            .get()?
+            .argsort_within(feature, descending)?
+            .view())
+    }
+
+    pub fn topk(&self, feature: usize, k: usize, largest: bool) -> PyResult<RaggedBufferView<T>> {
+        Ok(self.contiguous()?.get()?.topk(feature, k, largest)?.view())
     }
-    Ok(())
 }
 
-impl<T: numpy::Element + Copy + Display + std::fmt::Debug + PartialEq> PartialEq
+impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Sync + ToF64> RaggedBufferView<T> {
+    /// Elementwise `|self - other| <= atol + rtol * |other|`, with the same broadcasting rules as `binop`.
+    /// NaNs are considered close to each other if `equal_nan` is set.
+    pub fn isclose(
+        &self,
+        other: &RaggedBufferView<T>,
+        rtol: f64,
+        atol: f64,
+        equal_nan: bool,
+    ) -> PyResult<RaggedBufferView<bool>> {
+        self.zip_map(other, |lhs, rhs| {
+            isclose(lhs.to_f64(), rhs.to_f64(), rtol, atol, equal_nan)
+        })
+    }
+
+    /// Whether all elements of `self` and `other` are close according to `isclose`.
+    pub fn allclose(
+        &self,
+        other: &RaggedBufferView<T>,
+        rtol: f64,
+        atol: f64,
+        equal_nan: bool,
+    ) -> PyResult<bool> {
+        let close = self.isclose(other, rtol, atol, equal_nan)?;
+        let close = close.get()?;
+        Ok(close.data.iter().all(|&x| x))
+    }
+}
+
+/// Numeric types that can be compared approximately with `isclose`.
+pub trait ToF64 {
+    fn to_f64(self) -> f64;
+}
+
+impl ToF64 for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl ToF64 for i64 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+fn isclose(lhs: f64, rhs: f64, rtol: f64, atol: f64, equal_nan: bool) -> bool {
+    if lhs == rhs {
+        true
+    } else if lhs.is_nan() || rhs.is_nan() {
+        equal_nan && lhs.is_nan() && rhs.is_nan()
+    } else {
+        (lhs - rhs).abs() <= atol + rtol * rhs.abs()
+    }
+}
+
+impl RaggedBufferView<i64> {
+    // Converts a buffer with one sequence holding the number of items in each part for each sequence of a split buffer
+    // into `lengths[p][i]`, the number of items of sequence `i` in part `p`.
+    fn part_lengths(&self) -> PyResult<Vec<Vec<usize>>> {
+        let guard = self.get()?;
+        let lengths = ResolvedView::new(&guard, self.slices_of(&guard));
+        if lengths.features.len() != 1 {
+            return Err(Error::generic(format!(
+                "Split lengths must have a single feature, but have {}",
+                lengths.features.len()
+            ))
+            .into());
+        }
+        let parts = lengths.lengths.first().cloned().unwrap_or(0);
+        if lengths.lengths.iter().any(|&len| len != parts) {
+            return Err(Error::generic(format!(
+                "Split lengths must have the same number of parts for every sequence, got {:?}",
+                lengths.lengths
+            ))
+            .into());
+        }
+        let mut result = vec![Vec::with_capacity(lengths.sequences.len()); parts];
+        for iseq in 0..lengths.sequences.len() {
+            for (part, item) in result.iter_mut().zip(lengths.items_of(iseq)) {
+                let len = lengths.get(item, 0);
+                part.push(usize::try_from(len).map_err(|_| {
+                    Error::generic(format!("Split sizes must be non-negative, got {}", len))
+                })?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+// Views that are no longer valid compare unequal to everything, including themselves, so there is no `Eq` impl.
+impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Sync + PartialEq> PartialEq
     for RaggedBufferView<T>
 {
     fn eq(&self, other: &RaggedBufferView<T>) -> bool {
-        // TODO: implement for views
-        self.require_contiguous("eq").unwrap();
-        other.require_contiguous("eq").unwrap();
-        *self.get() == *other.get()
+        self.equals(other).unwrap_or(false)
     }
 }
 
-impl<T: numpy::Element + Copy + Display + std::fmt::Debug + Eq> Eq for RaggedBufferView<T> {}
-
 impl<T> RaggedBuffer<T> {
     pub fn view(self) -> RaggedBufferView<T> {
         RaggedBufferView {
-            inner: Arc::new(RwLock::new(self)),
+            inner: Storage::new(self),
             view: None,
+            generation: 0,
         }
     }
 }
@@ -0,0 +1,100 @@
+use crate::ragged_buffer::{Error, Result};
+
+/// Options for `minibatches`. At least one of `batch_size` and `max_items` must be set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MinibatchOptions {
+    /// Maximum number of sequences in each minibatch.
+    pub batch_size: Option<usize>,
+    /// Maximum total number of items in each minibatch.
+    /// A sequence with more items than `max_items` forms a minibatch on its own.
+    pub max_items: Option<usize>,
+    /// Number of buckets of sequences with similar lengths. Each minibatch only contains sequences from a single bucket.
+    pub buckets: Option<usize>,
+}
+
+/// Splits the sequences with the given `lengths` into randomly shuffled minibatches of sequence indices.
+///
+/// Every sequence is contained in exactly one minibatch. The result only depends on `lengths`, `options`, and `seed`.
+pub fn minibatches(
+    lengths: &[usize],
+    options: &MinibatchOptions,
+    seed: u64,
+) -> Result<Vec<Vec<usize>>> {
+    if options.batch_size.is_none() && options.max_items.is_none() {
+        return Err(Error::generic(
+            "At least one of batch_size and max_items must be specified",
+        ));
+    }
+    for (name, value) in [
+        ("batch_size", options.batch_size),
+        ("max_items", options.max_items),
+        ("buckets", options.buckets),
+    ] {
+        if value == Some(0) {
+            return Err(Error::generic(format!("{} must be positive", name)));
+        }
+    }
+
+    let mut rng = SplitMix64(seed);
+    let mut order = (0..lengths.len()).collect::<Vec<_>>();
+    rng.shuffle(&mut order);
+    let mut buckets = match options.buckets {
+        Some(buckets) => {
+            // Sorting is stable, so sequences of the same length remain shuffled.
+            order.sort_by_key(|&i| lengths[i]);
+            (0..buckets)
+                .map(|b| order[b * order.len() / buckets..(b + 1) * order.len() / buckets].to_vec())
+                .collect::<Vec<_>>()
+        }
+        None => vec![order],
+    };
+
+    let mut batches = Vec::new();
+    for bucket in &mut buckets {
+        rng.shuffle(bucket);
+        let mut batch = Vec::new();
+        let mut items = 0;
+        for &i in bucket.iter() {
+            let full = options.batch_size.is_some_and(|size| batch.len() >= size)
+                || options
+                    .max_items
+                    .is_some_and(|max| items + lengths[i] > max);
+            if full && !batch.is_empty() {
+                batches.push(std::mem::take(&mut batch));
+                items = 0;
+            }
+            batch.push(i);
+            items += lengths[i];
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+    }
+    rng.shuffle(&mut batches);
+    Ok(batches)
+}
+
+// Small deterministic random number generator, so that the order of minibatches for a given seed does not depend on external crates.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Returns a random integer in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
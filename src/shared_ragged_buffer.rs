@@ -0,0 +1,516 @@
+use std::ffi::CString;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use ndarray::ArrayView2;
+
+use crate::ragged_buffer::{Error, RaggedBuffer, Result};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"RAGGEDSH");
+const DTYPE_LEN: usize = 16;
+
+/// Element type of a `SharedRaggedBuffer`.
+pub trait SharedElement: Copy {
+    /// Representation of the element in shared memory.
+    /// Other processes can write arbitrary bytes to the segment, so every bit pattern must be a valid `Raw`.
+    type Raw: Copy;
+
+    fn to_raw(self) -> Self::Raw;
+
+    fn from_raw(raw: Self::Raw) -> Self;
+
+    /// Reinterprets elements in shared memory as `Self` without copying, if `Self` is stored as is.
+    fn cast_slice(raw: &[Self::Raw]) -> Option<&[Self]>;
+}
+
+macro_rules! impl_shared_element_identity {
+    ($($t:ty),*) => {
+        $(
+            impl SharedElement for $t {
+                type Raw = $t;
+
+                fn to_raw(self) -> $t {
+                    self
+                }
+
+                fn from_raw(raw: $t) -> $t {
+                    raw
+                }
+
+                fn cast_slice(raw: &[$t]) -> Option<&[$t]> {
+                    Some(raw)
+                }
+            }
+        )*
+    };
+}
+
+impl_shared_element_identity!(f32, i64);
+
+// Not every byte is a valid `bool`, so booleans are stored as `u8`.
+impl SharedElement for bool {
+    type Raw = u8;
+
+    fn to_raw(self) -> u8 {
+        self as u8
+    }
+
+    fn from_raw(raw: u8) -> bool {
+        raw != 0
+    }
+
+    fn cast_slice(_: &[u8]) -> Option<&[bool]> {
+        None
+    }
+}
+
+// Layout of the shared memory segment:
+// | Header | offsets: [u64; max_sequences + 1] | data: [T::Raw; max_items * features] |
+// `offsets[i]..offsets[i + 1]` gives the range of items of sequence `i`.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    dtype: [u8; DTYPE_LEN],
+    elem_size: u64,
+    features: u64,
+    max_sequences: u64,
+    max_items: u64,
+    // Incremented by every `reset`. Readers compare generations before and after
+    // accessing the buffer to detect that the writer started a new step.
+    generation: AtomicU64,
+    // Published with release ordering after the corresponding offsets and data are written.
+    size0: AtomicU64,
+    items: AtomicU64,
+}
+
+/// RaggedBuffer whose data and offsets live in a named POSIX shared memory segment with fixed capacity.
+///
+/// One process (the writer) pushes sequences into the buffer and calls `reset` between steps.
+/// Any number of other processes can `attach` to the segment by name and read its contents.
+/// Attached buffers are read-only.
+pub struct SharedRaggedBuffer<T> {
+    name: String,
+    ptr: *mut u8,
+    len: usize,
+    owner: bool,
+    writable: bool,
+    _marker: PhantomData<T>,
+}
+
+// Only the handle returned by `create` maps the segment writable, and it only mutates the segment
+// through `&mut self`. Attached handles map the segment read-only and never write to it.
+// Other processes may modify the segment at any time, so it is never exposed as a safe reference:
+// reads copy out of the segment and are validated against the generation counter.
+unsafe impl<T: Send> Send for SharedRaggedBuffer<T> {}
+unsafe impl<T: Sync> Sync for SharedRaggedBuffer<T> {}
+
+impl<T: SharedElement + Display + std::fmt::Debug> SharedRaggedBuffer<T> {
+    /// Creates a new shared memory segment with capacity for `max_sequences` sequences and `max_items` items.
+    /// The segment is unlinked when the creating `SharedRaggedBuffer` is dropped.
+    pub fn create(
+        name: &str,
+        features: usize,
+        max_sequences: usize,
+        max_items: usize,
+    ) -> Result<Self> {
+        let len = Self::segment_len(features, max_sequences, max_items)?;
+        let cname = shm_name(name)?;
+        // `shm_open` is variadic on some platforms, so the mode is passed as `c_uint`.
+        let fd = unsafe {
+            libc::shm_open(
+                cname.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(os_error(&format!(
+                "Failed to create shared memory {}",
+                name
+            )));
+        }
+        if unsafe { libc::ftruncate(fd, len as libc::off_t) } != 0 {
+            let err = os_error(&format!("Failed to resize shared memory {}", name));
+            unsafe {
+                libc::close(fd);
+                libc::shm_unlink(cname.as_ptr());
+            }
+            return Err(err);
+        }
+        let ptr = match map(fd, len, libc::PROT_READ | libc::PROT_WRITE) {
+            Ok(ptr) => ptr,
+            Err(err) => {
+                unsafe { libc::shm_unlink(cname.as_ptr()) };
+                return Err(err);
+            }
+        };
+        let mut dtype = [0u8; DTYPE_LEN];
+        for (d, b) in dtype.iter_mut().zip(std::any::type_name::<T>().bytes()) {
+            *d = b;
+        }
+        unsafe {
+            std::ptr::write(
+                ptr as *mut Header,
+                Header {
+                    magic: MAGIC,
+                    dtype,
+                    elem_size: std::mem::size_of::<T::Raw>() as u64,
+                    features: features as u64,
+                    max_sequences: max_sequences as u64,
+                    max_items: max_items as u64,
+                    generation: AtomicU64::new(0),
+                    size0: AtomicU64::new(0),
+                    items: AtomicU64::new(0),
+                },
+            );
+            *(ptr.add(header_len()) as *mut u64) = 0;
+        }
+        Ok(SharedRaggedBuffer {
+            name: name.to_string(),
+            ptr,
+            len,
+            owner: true,
+            writable: true,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Attaches to an existing shared memory segment created by `create`.
+    /// The returned buffer is read-only: `push`, `push_empty`, and `reset` fail.
+    pub fn attach(name: &str) -> Result<Self> {
+        let cname = shm_name(name)?;
+        let fd = unsafe { libc::shm_open(cname.as_ptr(), libc::O_RDONLY, 0 as libc::c_uint) };
+        if fd < 0 {
+            return Err(os_error(&format!("Failed to open shared memory {}", name)));
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            let err = os_error(&format!("Failed to stat shared memory {}", name));
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        let len = stat.st_size as usize;
+        if len < header_len() + std::mem::size_of::<u64>() {
+            unsafe { libc::close(fd) };
+            return Err(Error::generic(format!(
+                "Shared memory {} is too small to contain a RaggedBuffer",
+                name
+            )));
+        }
+        let buffer = SharedRaggedBuffer {
+            name: name.to_string(),
+            ptr: map(fd, len, libc::PROT_READ)?,
+            len,
+            owner: false,
+            writable: false,
+            _marker: PhantomData,
+        };
+        let header = buffer.header();
+        if header.magic != MAGIC {
+            return Err(Error::generic(format!(
+                "Shared memory {} does not contain a RaggedBuffer",
+                name
+            )));
+        }
+        let dtype = std::any::type_name::<T>();
+        let stored = header
+            .dtype
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect::<String>();
+        if header.elem_size != std::mem::size_of::<T::Raw>() as u64 || !dtype.starts_with(&stored) {
+            return Err(Error::generic(format!(
+                "Shared memory {} contains elements of type {}, expected {}",
+                name, stored, dtype
+            )));
+        }
+        let expected = Self::segment_len(
+            header.features as usize,
+            header.max_sequences as usize,
+            header.max_items as usize,
+        )?;
+        if expected != len {
+            return Err(Error::generic(format!(
+                "Shared memory {} has size {}, but header specifies size {}",
+                name, len, expected
+            )));
+        }
+        Ok(buffer)
+    }
+
+    fn segment_len(features: usize, max_sequences: usize, max_items: usize) -> Result<usize> {
+        max_items
+            .checked_mul(features)
+            .and_then(|n| n.checked_mul(std::mem::size_of::<T::Raw>()))
+            .and_then(|data| {
+                (max_sequences.checked_add(1)?)
+                    .checked_mul(std::mem::size_of::<u64>())?
+                    .checked_add(data)?
+                    .checked_add(header_len())
+            })
+            .ok_or_else(|| Error::generic("Shared memory capacity overflows usize"))
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    fn offsets_ptr(&self) -> *mut u64 {
+        unsafe { self.ptr.add(header_len()) as *mut u64 }
+    }
+
+    fn data_ptr(&self) -> *mut T::Raw {
+        let offsets_len = (self.max_sequences() + 1) * std::mem::size_of::<u64>();
+        unsafe { self.ptr.add(header_len() + offsets_len) as *mut T::Raw }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn features(&self) -> usize {
+        self.header().features as usize
+    }
+
+    pub fn max_sequences(&self) -> usize {
+        self.header().max_sequences as usize
+    }
+
+    pub fn max_items(&self) -> usize {
+        self.header().max_items as usize
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.header().generation.load(Ordering::Acquire)
+    }
+
+    pub fn size0(&self) -> usize {
+        self.header().size0.load(Ordering::Acquire) as usize
+    }
+
+    pub fn items(&self) -> usize {
+        self.header().items.load(Ordering::Acquire) as usize
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn require_writable(&self) -> Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(Error::generic(format!(
+                "Shared RaggedBuffer {} is attached read-only and cannot be modified",
+                self.name
+            )))
+        }
+    }
+
+    pub fn push(&mut self, data: &ArrayView2<T>) -> Result<()> {
+        self.require_writable()?;
+        if data.dim().1 != self.features() {
+            return Err(Error::generic(format!(
+                "Features mismatch: {} != {}",
+                self.features(),
+                data.dim().1
+            )));
+        }
+        let size0 = self.size0();
+        let items = self.items();
+        if size0 == self.max_sequences() {
+            return Err(Error::generic(format!(
+                "Shared RaggedBuffer is full: capacity of {} sequences exceeded",
+                self.max_sequences()
+            )));
+        }
+        if items + data.dim().0 > self.max_items() {
+            return Err(Error::generic(format!(
+                "Shared RaggedBuffer is full: pushing {} items exceeds capacity of {} items ({} used)",
+                data.dim().0,
+                self.max_items(),
+                items
+            )));
+        }
+        let features = self.features();
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.data_ptr().add(items * features),
+                data.dim().0 * features,
+            )
+        };
+        for (d, x) in dst.iter_mut().zip(data.iter()) {
+            *d = x.to_raw();
+        }
+        self.publish(size0, items + data.dim().0);
+        Ok(())
+    }
+
+    pub fn push_empty(&mut self) -> Result<()> {
+        self.require_writable()?;
+        let size0 = self.size0();
+        if size0 == self.max_sequences() {
+            return Err(Error::generic(format!(
+                "Shared RaggedBuffer is full: capacity of {} sequences exceeded",
+                self.max_sequences()
+            )));
+        }
+        self.publish(size0, self.items());
+        Ok(())
+    }
+
+    fn publish(&mut self, size0: usize, items: usize) {
+        unsafe { *self.offsets_ptr().add(size0 + 1) = items as u64 };
+        let header = self.header();
+        header.items.store(items as u64, Ordering::Release);
+        header.size0.store(size0 as u64 + 1, Ordering::Release);
+    }
+
+    /// Removes all sequences and starts a new generation. Returns the new generation.
+    pub fn reset(&mut self) -> Result<u64> {
+        self.require_writable()?;
+        let header = self.header();
+        header.size0.store(0, Ordering::Release);
+        header.items.store(0, Ordering::Release);
+        Ok(header.generation.fetch_add(1, Ordering::AcqRel) + 1)
+    }
+
+    /// Copies the item offsets of all sequences (`size0() + 1` elements).
+    /// The offsets are not validated and may be inconsistent if the writer calls `reset` concurrently.
+    pub fn offsets(&self) -> Vec<u64> {
+        let len = self.size0().min(self.max_sequences()) + 1;
+        unsafe { copy_from(self.offsets_ptr(), len) }
+    }
+
+    /// Zero-copy access to the flattened data of all items (`items() * features()` elements).
+    /// Returns `None` if elements of type `T` are not stored as is (see `SharedElement::cast_slice`).
+    ///
+    /// # Safety
+    ///
+    /// The writer may modify the returned slice at any time after calling `reset`.
+    /// The caller must not read from the slice once `generation()` has changed.
+    pub unsafe fn data(&self) -> Option<&[T]> {
+        let items = self.items().min(self.max_items());
+        T::cast_slice(std::slice::from_raw_parts(
+            self.data_ptr(),
+            items * self.features(),
+        ))
+    }
+
+    pub fn lengths(&self) -> Result<Vec<i64>> {
+        self.read_consistent(|subarrays| {
+            subarrays.iter().map(|r| (r.end - r.start) as i64).collect()
+        })
+    }
+
+    /// Copies the current contents into a `RaggedBuffer`.
+    /// Fails if the writer calls `reset` while the copy is in progress.
+    pub fn to_ragged_buffer(&self) -> Result<RaggedBuffer<T>> {
+        self.read_consistent(|subarrays| {
+            let items = subarrays.last().map(|r| r.end).unwrap_or(0);
+            RaggedBuffer {
+                data: unsafe { copy_from(self.data_ptr(), items * self.features()) }
+                    .into_iter()
+                    .map(T::from_raw)
+                    .collect(),
+                subarrays,
+                features: self.features(),
+            }
+        })
+    }
+
+    // Reads the offsets of all sequences and passes them to `read`.
+    // Fails if the offsets are inconsistent or the generation changes before `read` returns.
+    fn read_consistent<R>(&self, read: impl FnOnce(Vec<Range<usize>>) -> R) -> Result<R> {
+        let generation = self.generation();
+        let offsets = self.offsets();
+        let mut subarrays = Vec::with_capacity(offsets.len() - 1);
+        for w in offsets.windows(2) {
+            if w[0] > w[1] || w[1] > self.max_items() as u64 {
+                return Err(self.modified_error(generation));
+            }
+            subarrays.push(w[0] as usize..w[1] as usize);
+        }
+        let result = read(subarrays);
+        fence(Ordering::Acquire);
+        if self.generation() != generation {
+            return Err(self.modified_error(generation));
+        }
+        Ok(result)
+    }
+
+    fn modified_error(&self, generation: u64) -> Error {
+        Error::generic(format!(
+            "Shared RaggedBuffer {} was reset while being read (generation {} -> {})",
+            self.name,
+            generation,
+            self.generation()
+        ))
+    }
+
+    /// Removes the name of the shared memory segment. Existing mappings remain valid.
+    pub fn unlink(&mut self) -> Result<()> {
+        let cname = shm_name(&self.name)?;
+        self.owner = false;
+        if unsafe { libc::shm_unlink(cname.as_ptr()) } != 0 {
+            return Err(os_error(&format!(
+                "Failed to unlink shared memory {}",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for SharedRaggedBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+        if self.owner {
+            if let Ok(cname) = shm_name(&self.name) {
+                unsafe { libc::shm_unlink(cname.as_ptr()) };
+            }
+        }
+    }
+}
+
+fn header_len() -> usize {
+    // Round up to 8 bytes so that offsets and data are aligned.
+    std::mem::size_of::<Header>().div_ceil(8) * 8
+}
+
+fn shm_name(name: &str) -> Result<CString> {
+    let name = if name.starts_with('/') {
+        name.to_string()
+    } else {
+        format!("/{}", name)
+    };
+    CString::new(name).map_err(|_| Error::generic("Shared memory name must not contain null bytes"))
+}
+
+// Copies `len` elements that another process may be writing concurrently.
+unsafe fn copy_from<T: Copy>(src: *const T, len: usize) -> Vec<T> {
+    let mut result = Vec::with_capacity(len);
+    std::ptr::copy_nonoverlapping(src, result.as_mut_ptr(), len);
+    result.set_len(len);
+    result
+}
+
+fn map(fd: libc::c_int, len: usize, prot: libc::c_int) -> Result<*mut u8> {
+    let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0) };
+    let result = if ptr == libc::MAP_FAILED {
+        Err(os_error("Failed to map shared memory"))
+    } else {
+        Ok(ptr as *mut u8)
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+fn os_error(context: &str) -> Error {
+    Error::generic(format!("{}: {}", context, std::io::Error::last_os_error()))
+}
@@ -486,4 +486,889 @@ assert np.array_equal(
     np.array([0, 2], dtype=np.int64),
 ), f"{sliced.size1()}"
 
+# Test in-place arithmetic on contiguous buffers
+inplace = RaggedBufferI64.from_flattened(
+    np.array([[1, 2], [3, 4], [5, 6]], dtype=np.int64),
+    np.array([2, 1], dtype=np.int64),
+)
+inplace_alias = inplace[:, :, :]
+inplace += RaggedBufferI64.from_array(np.array([[[10, 20]], [[30, 40]]], dtype=np.int64))
+inplace *= 2
+inplace -= inplace[:, :, :]
+assert np.array_equal(inplace.as_array(), np.zeros((3, 2), dtype=np.int64))
+inplace += 1
+inplace *= inplace.clone()
+assert np.array_equal(inplace.as_array(), np.ones((3, 2), dtype=np.int64))
+assert inplace_alias.materialize() == inplace, f"{inplace_alias}"
+
+
+# Test broadcasting
+bcast = RaggedBufferF32.from_flattened(
+    np.array([[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]], dtype=np.float32),
+    np.array([2, 0, 1], dtype=np.int64),
+)
+assert np.array_equal(
+    (bcast + np.array([10.0, 100.0], dtype=np.float32)).as_array(),
+    np.array([[11.0, 102.0], [13.0, 104.0], [15.0, 106.0]], dtype=np.float32),
+)
+assert np.array_equal(
+    (bcast * np.array([[1.0, 1.0], [2.0, 2.0], [3.0, 3.0]], dtype=np.float32)).as_array(),
+    np.array([[1.0, 2.0], [3.0, 4.0], [15.0, 18.0]], dtype=np.float32),
+)
+single_feature = RaggedBufferF32.from_flattened(
+    np.array([[1.0], [2.0], [3.0]], dtype=np.float32),
+    np.array([2, 0, 1], dtype=np.int64),
+)
+assert np.array_equal(
+    (bcast * single_feature).as_array(),
+    np.array([[1.0, 2.0], [6.0, 8.0], [15.0, 18.0]], dtype=np.float32),
+)
+assert bcast * single_feature == single_feature * bcast
+bcast_inplace = bcast.clone()
+bcast_inplace -= np.array([1.0, 2.0], dtype=np.float32)
+assert np.array_equal(
+    bcast_inplace.as_array(),
+    np.array([[0.0, 0.0], [2.0, 2.0], [4.0, 4.0]], dtype=np.float32),
+)
+try:
+    bcast + np.zeros(3, dtype=np.float32)
+except ValueError as e:
+    assert "features" in str(e), str(e)
+else:
+    assert False, "Did not raise ValueError"
+
+
+# Test arithmetic on views
+rb = RaggedBufferF32.from_flattened(
+    np.arange(18, dtype=np.float32).reshape(6, 3),
+    np.array([2, 0, 4], dtype=np.int64),
+)
+other = RaggedBufferF32.from_flattened(
+    np.ones((6, 2), dtype=np.float32),
+    np.array([2, 0, 4], dtype=np.int64),
+)
+assert rb[:, :, 0:2] + other == (rb + 1.0)[:, :, 0:2].materialize()
+assert rb[:, :, 0:2] + rb[:, :, 1:3] == RaggedBufferF32.from_flattened(
+    np.arange(18, dtype=np.float32).reshape(6, 3)[:, 0:2]
+    + np.arange(18, dtype=np.float32).reshape(6, 3)[:, 1:3],
+    np.array([2, 0, 4], dtype=np.int64),
+)
+assert rb * rb[:, :, 2] == rb * rb[:, :, 2].materialize()
+assert rb[[2, 0], 0:1, :] - rb[[2, 0]] == RaggedBufferF32.from_flattened(
+    np.array(
+        [[0, 0, 0], [-3, -3, -3], [-6, -6, -6], [-9, -9, -9], [0, 0, 0], [-3, -3, -3]],
+        dtype=np.float32,
+    ),
+    np.array([4, 2], dtype=np.int64),
+)
+assert rb[:, :, 1] * 2.0 == (rb * 2.0)[:, :, 1].materialize()
+try:
+    rb[:, :, 0:2] + rb
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError"
+
+
+# Test slicing views
+rb = RaggedBufferF32.from_flattened(
+    np.arange(18, dtype=np.float32).reshape(6, 3),
+    np.array([2, 0, 4], dtype=np.int64),
+)
+view = rb[[2, 0], 1:, :][:, :, 1:][0:1, :, 0]
+assert view.materialize() == RaggedBufferF32.from_flattened(
+    np.array([[10], [13], [16]], dtype=np.float32),
+    np.array([3], dtype=np.int64),
+)
+assert rb[:, 1:][[2, 0]] == RaggedBufferF32.from_flattened(
+    np.array([[9, 10, 11], [12, 13, 14], [15, 16, 17], [3, 4, 5]], dtype=np.float32),
+    np.array([3, 1], dtype=np.int64),
+)
+assert rb[:, ::2, :][:, 1:, :].materialize() == RaggedBufferF32.from_flattened(
+    np.array([[12, 13, 14]], dtype=np.float32),
+    np.array([0, 0, 1], dtype=np.int64),
+)
+assert rb[:, :, 1:][2] == RaggedBufferF32.from_flattened(
+    np.array([[7, 8], [10, 11], [13, 14], [16, 17]], dtype=np.float32),
+    np.array([4], dtype=np.int64),
+)
+try:
+    rb[:, :, 1:][:, :, 2]
+except IndexError:
+    pass
+else:
+    assert False, "Did not raise IndexError"
+
+
+# Test shape of views
+rb = RaggedBufferF32.from_flattened(
+    np.arange(18, dtype=np.float32).reshape(6, 3),
+    np.array([2, 0, 4], dtype=np.int64),
+)
+view = rb[[2, 1, 0], [0, 2, 3], 1:]
+assert view.size0() == 3
+assert np.array_equal(view.size1(), np.array([3, 0, 1], dtype=np.int64))
+assert view.size1(0) == 3
+assert view.size1(2) == 1
+assert view.size2() == 2
+assert view.items() == 4
+assert len(view) == 8
+view = rb[:, 1::2, :]
+assert np.array_equal(view.size1(), np.array([1, 0, 2], dtype=np.int64))
+assert view.items() == 3
+assert view.materialize().items() == 3
+
+
+# Test item assignment
+def make_rb():
+    return RaggedBufferF32.from_flattened(
+        np.arange(18, dtype=np.float32).reshape(6, 3),
+        np.array([2, 0, 4], dtype=np.int64),
+    )
+
+
+rb = make_rb()
+rb[:, :, 2] = 0.0
+expected = np.arange(18, dtype=np.float32).reshape(6, 3)
+expected[:, 2] = 0.0
+assert np.array_equal(rb.as_array(), expected)
+
+rb = make_rb()
+rb[0] = np.array([[-1, -2, -3], [-4, -5, -6]], dtype=np.float32)
+expected = np.arange(18, dtype=np.float32).reshape(6, 3)
+expected[0:2] = -np.arange(1, 7, dtype=np.float32).reshape(2, 3)
+assert np.array_equal(rb.as_array(), expected)
+
+rb = make_rb()
+view = rb[:, :, 0:2]
+rb[[2, 0], 1:, 0:2] = RaggedBufferF32.from_flattened(
+    np.full((4, 2), -1, dtype=np.float32), np.array([3, 1], dtype=np.int64)
+)
+expected = np.arange(18, dtype=np.float32).reshape(6, 3)
+expected[[1, 3, 4, 5], 0:2] = -1
+assert np.array_equal(rb.as_array(), expected)
+assert np.array_equal(view.as_array(), expected[:, 0:2])
+
+rb = make_rb()
+mask = RaggedBufferBool.from_flattened(
+    rb.as_array()[:, :1] > 8, np.array([2, 0, 4], dtype=np.int64)
+)
+rb[mask] = 0.0
+expected = np.arange(18, dtype=np.float32).reshape(6, 3)
+expected[3:] = 0.0
+assert np.array_equal(rb.as_array(), expected)
+
+rb = make_rb()
+try:
+    rb[:, :, 0:2] = np.zeros(3, dtype=np.float32)
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError"
+
+
+# Test in-place operations on views that share storage
+rb = make_rb()
+rb[:, :, 0:2] -= rb[:, :, 1:3]
+expected = np.arange(18, dtype=np.float32).reshape(6, 3)
+expected[:, 0:2] -= np.arange(18, dtype=np.float32).reshape(6, 3)[:, 1:3]
+assert np.array_equal(rb.as_array(), expected)
+
+rb = make_rb()
+rb += rb.materialize()
+assert np.array_equal(
+    rb.as_array(), 2 * np.arange(18, dtype=np.float32).reshape(6, 3)
+)
+
+rb = make_rb()
+rb.extend(rb.materialize())
+assert rb.size0() == 6
+
+rb = RaggedBufferF32.from_flattened(
+    np.array(
+        [[1, 0, 1, 0], [2, 2, 0, 1], [3, 4, 0, 1]],
+        dtype=np.float32,
+    ),
+    np.array([1, 2], dtype=np.int64),
+)
+ragged_buffer.translate_rotate(rb[:, :, 0:2], rb[:, 0:1, 0:2], rb[:, 0:1, 2:4])
+assert np.array_equal(
+    rb.as_array(),
+    np.array(
+        [[0, 0, 1, 0], [0, 0, 0, 1], [2, -1, 0, 1]],
+        dtype=np.float32,
+    ),
+)
+
+
+# Test that changing the shape of a buffer invalidates views
+rb = make_rb()
+view = rb[:, :, 0]
+copy = rb.materialize()
+rb.push(np.zeros((1, 3), dtype=np.float32))
+try:
+    view.as_array()
+except RuntimeError:
+    pass
+else:
+    assert False, "Did not raise RuntimeError"
+assert copy.size0() == 3
+assert rb.size0() == 4
+assert np.array_equal(rb[:, :, 0].as_array(), rb.as_array()[:, :1])
+
+rb = make_rb()
+view = rb[0:1, :, :]
+view.push(np.zeros((1, 3), dtype=np.float32))
+assert view.size0() == 2
+assert rb.size0() == 3
+view[:, :, 0] = -1.0
+assert np.array_equal(rb.as_array(), np.arange(18, dtype=np.float32).reshape(6, 3))
+
+rb = make_rb()
+view = rb[1:, :, :]
+rb.clear()
+try:
+    view.size0()
+except RuntimeError:
+    pass
+else:
+    assert False, "Did not raise RuntimeError"
+
+
+# Test frozen buffers
+rb = make_rb()
+view = rb[:, :, 1:]
+view.freeze()
+assert rb.is_frozen()
+assert view.is_frozen()
+for op in [
+    lambda: rb.push(np.zeros((1, 3), dtype=np.float32)),
+    lambda: rb.push_empty(),
+    lambda: rb.extend(make_rb()),
+    lambda: rb.clear(),
+    lambda: rb.__isub__(1.0),
+    lambda: view.__iadd__(view),
+    lambda: view.push_empty(),
+    lambda: rb.__setitem__((slice(None), slice(None), 0), 0.0),
+]:
+    try:
+        op()
+    except ValueError:
+        pass
+    else:
+        assert False, "Did not raise ValueError"
+assert np.array_equal(rb.as_array(), np.arange(18, dtype=np.float32).reshape(6, 3))
+assert np.array_equal((view + 1.0).as_array(), rb.as_array()[:, 1:] + 1.0)
+
+copy = rb.clone()
+assert not copy.is_frozen()
+copy -= 1.0
+assert np.array_equal(
+    copy.as_array(), np.arange(18, dtype=np.float32).reshape(6, 3) - 1.0
+)
+
+frozen_origin = RaggedBufferF32.from_array(np.zeros((3, 1, 2), dtype=np.float32))
+frozen_origin.freeze()
+entities = RaggedBufferF32.from_array(np.ones((3, 2, 2), dtype=np.float32))
+entities.freeze()
+try:
+    ragged_buffer.translate_rotate(entities, frozen_origin, frozen_origin + 1.0)
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError"
+
+
+# Test equality and approximate comparison of views
+rb = make_rb()
+view = rb[1:, ::2, 1:]
+assert view == view.materialize()
+assert view.materialize() == view
+assert view != rb[1:, ::2, :2]
+assert rb != view
+assert rb == make_rb()
+assert rb[:, :, :] == make_rb()
+try:
+    hash(view)
+except TypeError:
+    pass
+else:
+    assert False, "Did not raise TypeError"
+
+close = rb.isclose(rb + np.array([0.0, 1e-7, 1.0], dtype=np.float32))
+assert np.array_equal(
+    close.as_array(), np.tile(np.array([True, True, False]), (6, 1))
+)
+assert rb.allclose(rb + 1e-6, rtol=0.0, atol=1e-5)
+assert not rb.allclose(rb + 1e-6, rtol=0.0, atol=0.0)
+assert view.allclose(view.materialize() * 1.000001)
+nan = RaggedBufferF32.from_array(np.full((1, 2, 1), np.nan, dtype=np.float32))
+assert not nan.allclose(nan)
+assert nan.allclose(nan, equal_nan=True)
+try:
+    view.allclose(rb)
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError"
+
+
+# Test negative indices, negative steps and ellipsis
+rb = make_rb()
+flat = np.arange(18, dtype=np.float32).reshape(6, 3)
+assert np.array_equal(rb[::-1].as_array(), np.concatenate([flat[2:], flat[:2]]))
+assert np.array_equal(rb[-1].as_array(), flat[2:])
+assert np.array_equal(rb[-1:, :, :].as_array(), flat[2:])
+assert np.array_equal(
+    rb[:, ::-1].as_array(), np.concatenate([flat[1::-1], flat[:1:-1]])
+)
+assert np.array_equal(rb[:, -1].as_array(), flat[[1, 5]])
+assert np.array_equal(rb[:, -3:].as_array(), np.concatenate([flat[:2], flat[3:]]))
+assert np.array_equal(rb[:, -2::-2, ::-1].as_array(), flat[[0, 4, 2]][:, ::-1])
+assert np.array_equal(rb[..., -1].as_array(), flat[:, 2:])
+assert np.array_equal(rb[2, ...].as_array(), flat[2:])
+assert np.array_equal(rb[..., [-1, 0]].as_array(), flat[:, [2, 0]])
+assert np.array_equal(rb[[-1, 0], :, -2].as_array(), flat[[2, 3, 4, 5, 0, 1], 1:2])
+assert np.array_equal(rb[:, :, 1:][:, ::-1].as_array(), rb[:, ::-1, 1:].as_array())
+assert rb[:, ::-1].size1(0) == 2
+assert rb[:, -1].size1(1) == 0
+
+rb[:, -1] = 0.0
+expected = flat.copy()
+expected[[1, 5]] = 0.0
+assert np.array_equal(rb.as_array(), expected)
+
+rb = make_rb()
+rb[:, ::-1] = make_rb()
+assert np.array_equal(rb.as_array(), make_rb()[:, ::-1].as_array())
+
+for index in [(0, ..., 0, ...), (0, 0, 0, 0), -4, (slice(None), slice(None), -4)]:
+    try:
+        rb[index]
+    except IndexError:
+        pass
+    else:
+        assert False, f"Did not raise IndexError for {index}"
+
+
+# Test boolean masks and index arrays
+rb = make_rb()
+flat = np.arange(18, dtype=np.float32).reshape(6, 3)
+assert np.array_equal(rb[np.array([True, False, True])].as_array(), flat)
+assert rb[np.array([False, True, True])].size1().tolist() == [0, 4]
+assert np.array_equal(rb[[True, False, True]].as_array(), flat)
+assert rb[[False, True, True]].size1().tolist() == [0, 4]
+assert rb[[]].size0() == 0
+assert np.array_equal(
+    rb[:, :, np.array([True, False, True])].as_array(), flat[:, [0, 2]]
+)
+idx = RaggedBufferI64.from_flattened(
+    np.array([[1], [0], [-1], [0], [0]], dtype=np.int64),
+    np.array([3, 0, 2], dtype=np.int64),
+)
+assert np.array_equal(rb[:, idx].as_array(), flat[[1, 0, 1, 5, 2]])
+assert rb[:, idx].size1().tolist() == [3, 0, 2]
+assert np.array_equal(rb[:, idx, 1:].as_array(), flat[[1, 0, 1, 5, 2], 1:])
+assert np.array_equal(rb[::-1, idx[::-1]].as_array(), flat[[2, 2, 1, 0, 1]])
+assert np.array_equal(
+    rb[np.array([2, 0, -1]), np.array([-1, 1, 0])], flat[[5, 1, 2]]
+)
+assert np.array_equal(
+    rb[np.array([2, 0]), np.array([-1, 1]), 1:], flat[[5, 1], 1:]
+)
+
+for index in [
+    np.array([True, False]),
+    [True, False],
+    (slice(None), idx[:2]),
+    (slice(None), RaggedBufferI64.from_array(np.zeros((3, 1, 2), dtype=np.int64))),
+    (slice(None), RaggedBufferI64.from_array(np.full((3, 1, 1), 2, dtype=np.int64))),
+    (np.array([0, 1]), np.array([0, 0])),
+    (np.array([0]), np.array([0, 1])),
+]:
+    try:
+        rb[index]
+    except IndexError:
+        pass
+    else:
+        assert False, f"Did not raise IndexError for {index}"
+
+
+# Test conversion to padded arrays
+rb = make_rb()
+flat = np.arange(18, dtype=np.float32).reshape(6, 3)
+padded, mask = rb.to_padded()
+assert padded.shape == (3, 4, 3) and padded.dtype == np.float32
+assert np.array_equal(padded[0, :2], flat[:2])
+assert np.array_equal(padded[2], flat[2:])
+assert np.all(padded[0, 2:] == 0) and np.all(padded[1] == 0)
+assert np.array_equal(
+    mask, np.array([[1, 1, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1]], dtype=np.bool_)
+)
+assert np.array_equal(padded[mask], rb.as_array())
+
+padded, mask = rb.to_padded(max_len=3, pad_value=-1.0, side="left")
+assert np.array_equal(padded[0], np.concatenate([np.full((1, 3), -1.0), flat[:2]]))
+assert np.array_equal(padded[2], flat[2:5])
+assert np.array_equal(mask[0], [False, True, True])
+padded, mask = rb.to_padded(max_len=3, truncate="head")
+assert np.array_equal(padded[2], flat[3:6])
+assert np.array_equal(rb[:, ::-1].to_padded()[0][2], flat[:1:-1])
+assert rb.to_padded(max_len=0)[0].shape == (3, 0, 3)
+
+padded, mask = RaggedBufferBool.from_flattened(
+    np.ones((3, 1), dtype=np.bool_), np.array([1, 2], dtype=np.int64)
+).to_padded()
+assert np.array_equal(padded[:, :, 0], mask)
+
+for kwargs in [{"side": "middle"}, {"truncate": "left"}]:
+    try:
+        rb.to_padded(**kwargs)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for {kwargs}"
+
+
+# Test construction from padded arrays
+rb = make_rb()
+padded, mask = rb.to_padded(max_len=5, pad_value=-1.0)
+assert RaggedBufferF32.from_padded(padded, rb.size1()) == rb
+assert RaggedBufferF32.from_padded_mask(padded, mask) == rb
+padded, mask = rb.to_padded(side="left")
+assert RaggedBufferF32.from_padded_mask(padded, mask) == rb
+empty = RaggedBufferF32.from_padded(padded, np.zeros(3, dtype=np.int64))
+assert empty.size1().tolist() == [0, 0, 0]
+mask = np.array([[True, False, True, False], [False] * 4, [False, True, False, True]])
+sparse = RaggedBufferF32.from_padded_mask(padded, mask)
+assert sparse.size1().tolist() == [2, 0, 2]
+assert np.array_equal(sparse.as_array(), padded[mask])
+assert RaggedBufferI64.from_padded(
+    np.arange(6, dtype=np.int64).reshape(2, 3, 1), np.array([1, 3], dtype=np.int64)
+).as_array().tolist() == [[0], [3], [4], [5]]
+
+for args in [
+    (padded, np.array([2, 0], dtype=np.int64)),
+    (padded, np.array([2, 0, 5], dtype=np.int64)),
+    (padded, np.array([2, 0, -1], dtype=np.int64)),
+]:
+    try:
+        RaggedBufferF32.from_padded(*args)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for lengths {args[1]}"
+try:
+    RaggedBufferF32.from_padded_mask(padded, mask[:, :3])
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError for mismatched mask"
+
+
+# Test unpacking the result of padpack
+rb = RaggedBufferF32.from_flattened(
+    np.arange(28, dtype=np.float32).reshape(14, 2),
+    np.array([6, 3, 4, 0, 1], dtype=np.int64),
+)
+padpack = rb.padpack()
+assert padpack is not None
+padpack_index, padpack_batch, padpack_inverse_index = padpack
+assert padpack_inverse_index.shape == (14,)
+packed = rb.as_array()[padpack_index]
+packed[np.isnan(padpack_batch)] = -1.0
+unpacked = RaggedBufferF32.from_padpacked(packed, padpack_inverse_index, rb.size1())
+assert unpacked == rb
+assert np.array_equal(
+    RaggedBufferF32.from_padpacked(
+        packed * 2, padpack_inverse_index, rb.size1()
+    ).as_array(),
+    rb.as_array() * 2,
+)
+
+for lengths in [
+    np.array([6, 3], dtype=np.int64),
+    np.array([6, 3, 4, 0, 2], dtype=np.int64),
+    np.array([15, -1], dtype=np.int64),
+]:
+    try:
+        RaggedBufferF32.from_padpacked(packed, padpack_inverse_index, lengths)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for lengths {lengths}"
+
+
+# Test padpack options
+lengths = np.array([6, 3, 4, 0, 1, 5, 2], dtype=np.int64)
+rb = RaggedBufferI64.from_flattened(
+    np.arange(21, dtype=np.int64).reshape(21, 1), lengths
+)
+for strategy in ["greedy", "first_fit_decreasing", "best_fit"]:
+    for capacity in [None, 6, 10]:
+        padpack_index, padpack_batch, padpack_inverse_index = rb.padpack(
+            capacity=capacity, strategy=strategy, sentinel=-1
+        )
+        assert padpack_batch.dtype == np.int64
+        assert padpack_index.shape[1] == (capacity or 6)
+        assert np.array_equal(
+            padpack_index.flatten()[padpack_inverse_index], np.arange(21)
+        )
+        assert np.array_equal(
+            padpack_batch.flatten()[padpack_inverse_index],
+            np.repeat(np.arange(7), lengths),
+        )
+        assert (padpack_batch == -1).sum() == padpack_batch.size - 21
+        packed = rb.as_array()[padpack_index]
+        assert (
+            RaggedBufferI64.from_padpacked(packed, padpack_inverse_index, lengths)
+            == rb
+        )
+assert rb.padpack(strategy="first_fit_decreasing")[0].shape == (4, 6)
+assert rb.padpack(capacity=7, strategy="best_fit")[0].shape == (3, 7)
+
+equal = RaggedBufferF32.from_array(np.zeros((3, 2, 1), dtype=np.float32))
+assert equal.padpack() is None
+padpack_index, padpack_batch, _ = equal.padpack(always=True)
+assert padpack_index.shape == (3, 2)
+assert np.array_equal(padpack_batch, np.array([[0, 0], [1, 1], [2, 2]]))
+padpack_index, padpack_batch, _ = equal.padpack(capacity=4, sentinel=-2)
+assert np.array_equal(padpack_batch, np.array([[0, 0, 1, 1], [2, 2, -2, -2]]))
+assert RaggedBufferF32(1).padpack(always=True)[0].shape == (0, 0)
+
+for kwargs in [{"capacity": 5}, {"strategy": "worst_fit"}]:
+    try:
+        rb.padpack(**kwargs)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for {kwargs}"
+
+
+# Test attention metadata
+rb = make_rb()
+assert rb.cu_seqlens().tolist() == [0, 2, 2, 6]
+assert rb.max_seqlen() == 4
+assert rb.segment_ids().tolist() == [0, 0, 2, 2, 2, 2]
+assert rb[::-1, 1:].cu_seqlens().tolist() == [0, 3, 3, 4]
+assert rb[::-1, 1:].segment_ids().tolist() == [0, 0, 0, 2]
+assert RaggedBufferF32(2).cu_seqlens().tolist() == [0]
+assert RaggedBufferF32(2).max_seqlen() == 0
+
+lengths = np.array([6, 3, 4, 0, 1], dtype=np.int64)
+rb = RaggedBufferI64.from_flattened(np.zeros((14, 1), dtype=np.int64), lengths)
+_, padpack_batch, _ = rb.padpack()
+segment_ids = np.array([[0] * 6, [0, 0, 0, 1, -1, -1], [0, 0, 0, 0, -1, -1]])
+assert np.array_equal(ragged_buffer.padpack_segment_ids(padpack_batch), segment_ids)
+_, padpack_batch, _ = rb.padpack(sentinel=99)
+assert np.array_equal(
+    ragged_buffer.padpack_segment_ids(padpack_batch, sentinel=99),
+    np.where(segment_ids == -1, 99, segment_ids),
+)
+mask = ragged_buffer.padpack_attention_mask(padpack_batch, sentinel=99)
+assert mask.shape == (3, 6, 6) and mask.dtype == np.bool_
+valid = padpack_batch != 99
+expected = (
+    (padpack_batch[:, :, None] == padpack_batch[:, None, :])
+    & valid[:, :, None]
+    & valid[:, None, :]
+)
+assert np.array_equal(mask, expected)
+assert mask[1, :3, :3].all() and mask[1, 3, 3] and not mask[1, 0, 3]
+
+
+# Test minibatch sampling
+lengths = np.array([(i * 7) % 13 for i in range(40)], dtype=np.int64)
+rb = RaggedBufferF32.from_flattened(
+    np.arange(lengths.sum(), dtype=np.float32).reshape(-1, 1), lengths
+)
+for kwargs in [
+    {"batch_size": 8},
+    {"max_items": 30},
+    {"batch_size": 4, "max_items": 30, "buckets": 4},
+    {"max_items": 5},
+]:
+    batches = rb.minibatches(seed=7, **kwargs)
+    assert all(batch.dtype == np.int64 for batch in batches)
+    assert sorted(np.concatenate(batches).tolist()) == list(range(40))
+    for batch in batches:
+        assert len(batch) <= kwargs.get("batch_size", 40)
+        assert len(batch) == 1 or lengths[batch].sum() <= kwargs.get("max_items", 1000)
+        assert rb[batch].size1().tolist() == lengths[batch].tolist()
+    other = rb.minibatches(seed=7, **kwargs)
+    assert all(np.array_equal(a, b) for a, b in zip(batches, other))
+    assert len(batches) == len(other)
+assert [b.tolist() for b in rb.minibatches(0, batch_size=8)] != [
+    b.tolist() for b in rb.minibatches(1, batch_size=8)
+]
+bucketed = rb.minibatches(seed=3, batch_size=10, buckets=4)
+assert sorted(lengths[b].max() - lengths[b].min() for b in bucketed)[-1] <= 3
+assert rb[::2].minibatches(seed=0, batch_size=100)[0].shape == (20,)
+assert RaggedBufferF32(1).minibatches(seed=0, batch_size=4) == []
+
+for kwargs in [{}, {"batch_size": 0}, {"batch_size": 4, "buckets": 0}]:
+    try:
+        rb.minibatches(seed=0, **kwargs)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for {kwargs}"
+
+
+# Test sorting within sequences
+rb = RaggedBufferF32.from_flattened(
+    np.array(
+        [[3, 0], [1, 1], [np.nan, 2], [1, 3], [5, 4], [2, 5], [7, 6]],
+        dtype=np.float32,
+    ),
+    np.array([4, 0, 3], dtype=np.int64),
+)
+assert rb.argsort_within(0).as_array()[:, 0].tolist() == [1, 3, 0, 2, 1, 0, 2]
+order = rb.argsort_within(0, descending=True)
+assert order.as_array()[:, 0].tolist() == [2, 0, 1, 3, 2, 0, 1]
+assert rb.argsort_within(0).size1().tolist() == [4, 0, 3]
+sorted_rb = rb.sort_by_feature(0)
+assert sorted_rb.as_array()[:, 1].tolist() == [1, 3, 0, 2, 5, 4, 6]
+assert rb[:, rb.argsort_within(1, descending=True)] == rb.sort_by_feature(
+    1, descending=True
+)
+top = rb.topk(0, 2)
+assert top.size1().tolist() == [2, 0, 2]
+assert top.as_array()[:, 1].tolist() == [2, 0, 6, 4]
+bottom = rb.topk(1, 5, largest=False)
+assert bottom == rb
+assert rb.topk(0, 0).size1().tolist() == [0, 0, 0]
+assert rb[::-1].topk(1, 1).as_array()[:, 1].tolist() == [6, 3]
+ints = RaggedBufferI64.from_flattened(
+    np.array([[2], [2], [1]], dtype=np.int64), np.array([3], dtype=np.int64)
+)
+assert ints.argsort_within(0, descending=True).as_array()[:, 0].tolist() == [0, 1, 2]
+try:
+    rb.topk(2, 1)
+except ValueError:
+    pass
+else:
+    assert False, "Did not raise ValueError for feature out of range"
+
+
+# Test sorting sequences by length
+rb = RaggedBufferI64.from_flattened(
+    np.arange(9, dtype=np.int64).reshape(9, 1),
+    np.array([3, 1, 4, 1, 0], dtype=np.int64),
+)
+assert rb.argsort_lengths().tolist() == [4, 1, 3, 0, 2]
+assert rb.argsort_lengths(descending=True).tolist() == [2, 0, 1, 3, 4]
+sorted_rb, undo = rb.sorted_by_length()
+assert sorted_rb.size1().tolist() == [0, 1, 1, 3, 4]
+assert sorted_rb == rb[rb.argsort_lengths()]
+assert sorted_rb[undo] == rb
+assert undo.tolist() == ragged_buffer.inverse_permutation(rb.argsort_lengths()).tolist()
+sorted_rb, undo = rb[::-1].sorted_by_length(descending=True)
+assert sorted_rb.size1().tolist() == [4, 3, 1, 1, 0]
+assert sorted_rb[undo] == rb[::-1]
+assert ragged_buffer.inverse_permutation(np.array([2, 0, 1])).tolist() == [1, 2, 0]
+assert ragged_buffer.inverse_permutation(np.array([], dtype=np.int64)).tolist() == []
+for permutation in [[0, 0], [1, 2], [-1, 0]]:
+    try:
+        ragged_buffer.inverse_permutation(np.array(permutation, dtype=np.int64))
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for {permutation}"
+
+
+# Test construction from segment ids
+items = np.arange(12, dtype=np.float32).reshape(6, 2)
+segment_ids = np.array([2, 0, 2, 3, 0, 2], dtype=np.int64)
+rb = RaggedBufferF32.from_segment_ids(items, segment_ids, 5)
+assert rb.size1().tolist() == [2, 0, 3, 1, 0]
+assert np.array_equal(rb.as_array(), items[[1, 4, 0, 2, 5, 3]])
+rb, permutation = RaggedBufferF32.from_segment_ids(
+    items, segment_ids, num_segments=5, return_permutation=True
+)
+assert permutation.tolist() == [1, 4, 0, 2, 5, 3]
+assert np.array_equal(rb.as_array(), items[permutation])
+assert np.array_equal(segment_ids[permutation], rb.segment_ids())
+empty = RaggedBufferI64.from_segment_ids(
+    np.zeros((0, 3), dtype=np.int64), np.zeros(0, dtype=np.int64), 2
+)
+assert empty.size1().tolist() == [0, 0] and empty.size2() == 3
+
+for ids, num_segments in [([2, 0, 2, 5, 0, 2], 5), ([0, 0, 0, 0, 0, -1], 5), ([0], 5)]:
+    try:
+        RaggedBufferF32.from_segment_ids(
+            items, np.array(ids, dtype=np.int64), num_segments
+        )
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for segment ids {ids}"
+
+
+# Test splitting buffers along each dimension
+a = make_rb()
+b = RaggedBufferF32.from_flattened(
+    np.arange(100, 112, dtype=np.float32).reshape(4, 3),
+    np.array([0, 3, 1], dtype=np.int64),
+)
+
+parts = ragged_buffer.cat([a, b, a], dim=0).split([3, 3, 3])
+assert len(parts) == 3
+assert parts[0] == a and parts[1] == b and parts[2] == a
+assert [p.size0() for p in ragged_buffer.cat([a, b]).split([0, 6, 0])] == [0, 6, 0]
+
+combined = ragged_buffer.cat([a, b], dim=1)
+parts = combined.split([a.size1(), b.size1()], dim=1)
+assert parts[0] == a and parts[1] == b
+parts = combined.split(a.size1(), dim=1)
+assert parts[0] == a and parts[1] == b
+parts = combined.split(a.size1().tolist(), dim=1)
+assert parts[0] == a and parts[1] == b
+lengths = RaggedBufferI64.from_array(
+    np.stack([a.size1(), b.size1()], axis=1)[:, :, None]
+)
+parts = combined.split(lengths, dim=1)
+assert parts[0] == a and parts[1] == b
+parts = combined.split([a.size1(), np.zeros(3, dtype=np.int64), b.size1()], dim=1)
+assert parts[1].size1().tolist() == [0, 0, 0] and parts[2] == b
+parts = combined[::-1].split(a.size1()[::-1].copy(), dim=1)
+assert parts[0] == a[::-1] and parts[1] == b[::-1]
+
+combined = ragged_buffer.cat([a, a * 2.0], dim=2)
+first, second = combined.split([3, 3], dim=2)
+assert first == a and second == a * 2.0
+first, second = combined.split([1, 5], dim=2)
+assert first == a[:, :, :1]
+assert np.array_equal(second.as_array()[:, :2], a.as_array()[:, 1:])
+
+for sizes, dim in [
+    ([3, 2], 0),
+    ([a.size1(), a.size1()], 1),
+    (np.array([3, 0, 0], dtype=np.int64), 1),
+    (np.array([-1, 0, 0], dtype=np.int64), 1),
+    ([a.size1()[:2]], 1),
+    (["2", "0", "4"], 1),
+    (
+        RaggedBufferI64.from_flattened(
+            np.array([[2], [0], [4], [3]], dtype=np.int64),
+            np.array([1, 1, 2], dtype=np.int64),
+        ),
+        1,
+    ),
+    (
+        RaggedBufferI64.from_array(
+            np.array([[[3], [-1]], [[0], [0]], [[4], [0]]], dtype=np.int64)
+        ),
+        1,
+    ),
+    ([3, 4], 2),
+    ([3], 3),
+]:
+    try:
+        a.split(sizes, dim=dim)
+    except ValueError:
+        pass
+    else:
+        assert False, f"Did not raise ValueError for {sizes} along dimension {dim}"
+
+
+# Test concurrent operations from multiple threads
+from concurrent.futures import ThreadPoolExecutor
+
+big = RaggedBufferF32.from_flattened(
+    np.arange(3000, dtype=np.float32).reshape(1000, 3),
+    np.full(100, 10, dtype=np.int64),
+)
+with ThreadPoolExecutor(max_workers=4) as executor:
+    results = list(executor.map(lambda i: (big * float(i)).as_array(), range(8)))
+for i, result in enumerate(results):
+    assert np.array_equal(result, big.as_array() * float(i))
+
+# In-place operations on two buffers in opposite order must not deadlock
+a = RaggedBufferF32.from_array(np.zeros((10, 10, 3), dtype=np.float32))
+b = RaggedBufferF32.from_array(np.zeros((10, 10, 3), dtype=np.float32))
+
+
+def add_repeatedly(lhs, rhs):
+    for _ in range(1000):
+        lhs += rhs
+        lhs[:, :, :1] += rhs[:, :, 1:2]
+        assert lhs == lhs.materialize()
+
+
+with ThreadPoolExecutor(max_workers=4) as executor:
+    futures = [
+        executor.submit(add_repeatedly, x, y) for x, y in [(a, b), (b, a)] * 2
+    ]
+    for future in futures:
+        future.result()
+
+
+# Test shared memory buffers
+if hasattr(ragged_buffer, "SharedRaggedBufferF32"):
+    import os
+
+    name = f"ragged-buffer-test-{os.getpid()}"
+    writer = ragged_buffer.SharedRaggedBufferF32.create(
+        name, features=2, max_sequences=3, max_items=4
+    )
+    reader = ragged_buffer.SharedRaggedBufferF32.attach(name)
+    writer.push(np.array([[1.0, 2.0], [3.0, 4.0]], dtype=np.float32))
+    writer.push_empty()
+    writer.push(np.array([[5.0, 6.0]], dtype=np.float32))
+    assert reader.size0() == 3
+    assert np.array_equal(reader.size1(), np.array([2, 0, 1], dtype=np.int64))
+    assert np.array_equal(
+        reader.as_array(),
+        np.array([[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]], dtype=np.float32),
+    ), f"{reader.as_array()}"
+    assert reader.to_ragged_buffer() == RaggedBufferF32.from_flattened(
+        np.array([[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]], dtype=np.float32),
+        np.array([2, 0, 1], dtype=np.int64),
+    )
+    try:
+        writer.push(np.zeros((2, 2), dtype=np.float32))
+    except ValueError:
+        pass
+    else:
+        assert False, "Did not raise ValueError"
+    assert writer.is_writable() and not reader.is_writable()
+    for mutate in [
+        lambda: reader.push(np.zeros((1, 2), dtype=np.float32)),
+        lambda: reader.push_empty(),
+        lambda: reader.reset(),
+    ]:
+        try:
+            mutate()
+        except ValueError:
+            pass
+        else:
+            assert False, "Did not raise ValueError"
+    assert not reader.as_array().flags.writeable
+    assert not writer.as_array().flags.writeable
+    assert reader.generation() == 0
+    assert writer.reset() == 1
+    assert reader.generation() == 1
+    assert reader.size0() == 0
+    try:
+        ragged_buffer.SharedRaggedBufferI64.attach(name)
+    except ValueError:
+        pass
+    else:
+        assert False, "Did not raise ValueError"
+    del reader
+    del writer
+
+    flags = np.array([[True, False], [False, True], [True, True]])
+    writer = ragged_buffer.SharedRaggedBufferBool.create(
+        name, features=2, max_sequences=2, max_items=3
+    )
+    writer.push(flags)
+    reader = ragged_buffer.SharedRaggedBufferBool.attach(name)
+    assert np.array_equal(reader.as_array(), flags)
+    assert reader.to_ragged_buffer() == RaggedBufferBool.from_array(flags[None])
+    del reader
+    del writer
+
+
 print("ALL TESTS PASSED")